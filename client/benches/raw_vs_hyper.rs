@@ -4,7 +4,10 @@ use criterion::{criterion_group, criterion_main, Criterion};
 use hyper::{Client, Uri};
 use tokio::{net::TcpStream, runtime::Runtime, sync::mpsc};
 
-use common::cli::{BenchmarkUpdate, ConnectionReport};
+use common::{
+    cli::{BenchmarkUpdate, ConnectionReport},
+    RequestSpec,
+};
 
 criterion_group!(benches, bench_http_requests);
 criterion_main!(benches);
@@ -39,7 +42,7 @@ async fn do_connect_and_request_hyper(requests: u64) {
     for n in 0..requests {
         let _ = common::cli::do_request(
             &client,
-            &Uri::from_str("http://127.0.0.1:8080/person").unwrap(),
+            &RequestSpec::get(Uri::from_str("http://127.0.0.1:8080/person").unwrap()),
             &mut conn_report,
             n,
             &tx,
@@ -3,7 +3,10 @@ use std::str::FromStr;
 use hyper::{Client, Uri};
 use tokio::{net::TcpStream, sync::mpsc};
 
-use common::cli::{BenchmarkUpdate, ConnectionReport};
+use common::{
+    cli::{BenchmarkUpdate, ConnectionReport},
+    RequestSpec,
+};
 
 async fn do_connect_and_request_raw(requests: u64) {
     let client = TcpStream::connect("127.0.0.1:8080").await.unwrap();
@@ -18,7 +21,7 @@ async fn do_connect_and_request_hyper(requests: u64) {
     for n in 0..requests {
         let _ = common::cli::do_request(
             &client,
-            &Uri::from_str("http://127.0.0.1:8080/person").unwrap(),
+            &RequestSpec::get(Uri::from_str("http://127.0.0.1:8080/person").unwrap()),
             &mut conn_report,
             n,
             &tx,
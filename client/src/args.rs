@@ -1,7 +1,28 @@
-use std::{ops::RangeInclusive, path::PathBuf, str::FromStr};
+use std::{fs, ops::RangeInclusive, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
 
+use anyhow::Context;
 use clap::Parser;
-use hyper::Uri;
+use hyper::{
+    header::{HeaderName, HeaderValue},
+    Method, Uri,
+};
+
+use regex::bytes::Regex;
+use serde_json::Value;
+
+use common::{
+    assertion::Assertion,
+    cli::{ErrorLimit, OverflowPolicy},
+    feeder::{FeedEnd, FeedOrder, Feeder},
+    histogram::{DEFAULT_PRECISION, PRECISION_RANGE},
+    json_path::JsonPath,
+    mix::RequestMix,
+    parse_duration,
+    profile::{LoadProfile, Stage},
+    scenario::Scenario,
+    tls::ClientIdentity,
+    Engine, HttpVersion, RequestSpec, StatusSet, Timeouts, TlsOptions,
+};
 
 #[derive(Parser, Clone)]
 #[command(author, version, about, long_about = None)]
@@ -12,10 +33,316 @@ pub struct Args {
     pub num_connections: u64,
     #[arg(short = 'r', long = "requests", default_value_t = 5000, value_parser = clap::value_parser!(u64).range(1..))]
     pub num_requests: u64,
+    /// Run for a fixed time (e.g. `30s`, `10m`, `1h`) instead of a fixed number of requests
+    #[arg(long, value_parser = is_duration_valid, conflicts_with = "num_requests")]
+    pub duration: Option<Duration>,
     #[arg(short = 'f', long = "file")]
     pub output_file: Option<PathBuf>,
     #[arg(short, long, default_value_t = 1000, env)]
     pub interval_ms: u64,
+    #[arg(short = 'X', long, default_value_t = Method::GET, value_parser = is_method_valid, env)]
+    pub method: Method,
+    #[arg(short = 'H', long = "header", value_name = "name: value", value_parser = is_header_valid)]
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    #[arg(short = 'd', long = "data", conflicts_with = "data_file")]
+    pub data: Option<String>,
+    #[arg(long = "data-file")]
+    pub data_file: Option<PathBuf>,
+    /// Spread the requests over the endpoints of a JSON file like
+    /// `[{"name": "read", "weight": 7, "url": "/items"}, ...]`, picked at random by weight. They
+    /// get the `--header`s, `--expect`ed statuses (unless they have an `expect`) and assertions
+    #[arg(long, value_name = "file", conflicts_with_all = ["method", "data", "data_file"])]
+    pub endpoints_file: Option<PathBuf>,
+    /// Let every connection run the steps of a JSON file like
+    /// `[{"name": "login", "method": "POST", "url": "/login", "think_time": "1s"}, ...]` in order,
+    /// as a virtual user. The steps have the fields of endpoints but no weight. A step can
+    /// `"extract": {"token": {"json": "$.token"}}` (or `regex`, `header`) from its response for the
+    /// `{{token}}`s in the URL, headers and body of later steps. `--requests` counts the iterations
+    /// (cli only)
+    #[arg(long, value_name = "file", conflicts_with_all = ["method", "data", "data_file", "endpoints_file", "rate"])]
+    pub scenario_file: Option<PathBuf>,
+    /// Fill the `{{column}}` placeholders in the URL, headers and body of the requests from the
+    /// rows of a `.csv` file with a header line, or of a `.ndjson` file with an object per line
+    #[arg(long, value_name = "file")]
+    pub feeder_file: Option<PathBuf>,
+    /// Take a row for every request (or iteration), `sequential`ly or at `random`, or a `unique`
+    /// one per connection that it keeps
+    #[arg(long, default_value = "sequential", requires = "feeder_file")]
+    pub feeder_order: FeedOrder,
+    /// What to do when every row was taken: `recycle` them, or `stop` the connections
+    #[arg(long, default_value = "recycle", requires = "feeder_file")]
+    pub on_feeder_end: FeedEnd,
+    /// Seed the random values of the template functions (e.g. `{{uuid()}}`), the picked endpoints
+    /// and the random rows, to repeat them in another run
+    #[arg(long, env)]
+    pub seed: Option<u64>,
+    /// Keep the cookies of the responses for every connection and send them along, following
+    /// their domain, path, expiry and `Secure` attributes (cli only)
+    #[arg(long, conflicts_with = "rate", env)]
+    pub cookies: bool,
+    /// Send requests at a fixed rate (e.g. `200/s` or `600/m`) instead of back to back
+    #[arg(long, value_name = "N/s", value_parser = is_rate_valid)]
+    pub rate: Option<f64>,
+    /// What to do with a scheduled request when all connections are busy (`delay` or `drop`)
+    #[arg(long, default_value = "delay", requires = "rate")]
+    pub on_full: OverflowPolicy,
+    /// Ramp the number of connections through stages like `2m:500,10m:500,1m:0`, starting at
+    /// `--connections`
+    #[arg(long, value_name = "duration:target", value_delimiter = ',', conflicts_with_all = ["rate", "duration", "stages_file"])]
+    pub stages: Vec<Stage>,
+    /// Read the stages from a file with one `duration:target` per line
+    #[arg(long, conflicts_with_all = ["rate", "duration"])]
+    pub stages_file: Option<PathBuf>,
+    /// Abort the run after this many errors, or this percentage of requests failing with an error
+    #[arg(long, value_name = "N|N%")]
+    pub max_errors: Option<ErrorLimit>,
+    /// Give up connecting after this time (e.g. `2s`)
+    #[arg(long, value_parser = is_duration_valid, env)]
+    pub connect_timeout: Option<Duration>,
+    /// Give up on a request, including its response body, after this time (the agent defaults to
+    /// `500ms`)
+    #[arg(long = "timeout", value_parser = is_duration_valid, env = "REQUEST_TIMEOUT")]
+    pub request_timeout: Option<Duration>,
+    /// Give up on a response body when no data arrived for this time
+    #[arg(long, value_parser = is_duration_valid, env)]
+    pub idle_timeout: Option<Duration>,
+    /// Trust the CA certificates in this PEM file instead of the built-in roots
+    #[arg(long = "cacert", value_name = "file", env = "CA_FILE")]
+    pub ca_file: Option<PathBuf>,
+    /// Don't verify the server certificate, e.g. for self-signed certificates
+    #[arg(short = 'k', long, env)]
+    pub insecure: bool,
+    /// Send this server name (SNI) and verify the certificate against it instead of the URL's host
+    #[arg(long = "sni", value_name = "name", env)]
+    pub server_name: Option<String>,
+    /// Present this client certificate (PEM) to servers that require one
+    #[arg(
+        long = "cert",
+        value_name = "file",
+        requires = "key_file",
+        env = "CLIENT_CERT"
+    )]
+    pub cert_file: Option<PathBuf>,
+    /// The private key (PEM) of `--cert`
+    #[arg(
+        long = "key",
+        value_name = "file",
+        requires = "cert_file",
+        env = "CLIENT_KEY"
+    )]
+    pub key_file: Option<PathBuf>,
+    /// Present the client certificate and key of this PKCS#12 archive to servers that require one
+    #[arg(
+        long = "p12",
+        value_name = "file",
+        conflicts_with = "cert_file",
+        env = "CLIENT_P12"
+    )]
+    pub pkcs12_file: Option<PathBuf>,
+    /// The password of `--p12`
+    #[arg(
+        long = "p12-password",
+        default_value = "",
+        requires = "pkcs12_file",
+        env = "CLIENT_P12_PASSWORD"
+    )]
+    pub pkcs12_password: String,
+    /// Speak HTTP `1.1` or `2` (h2c with prior knowledge for `http`, ALPN for `https` URLs)
+    #[arg(long, value_name = "version", default_value = "1.1", env)]
+    pub http_version: HttpVersion,
+    /// Concurrent requests on every connection, multiplexed as streams with HTTP/2
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..), env)]
+    pub streams: u64,
+    /// Send the requests with `hyper` or with the faster, HTTP/1.1 only `raw` client
+    #[arg(long, default_value = "hyper", env)]
+    pub engine: Engine,
+    /// Pipeline this many HTTP/1.1 requests on every connection (implies `--engine raw`)
+    #[arg(long, value_name = "depth", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "streams")]
+    pub pipeline: Option<u64>,
+    /// Open a new connection after this many requests (`1` for every request), to benchmark
+    /// connection establishment. The connect time is reported separately
+    #[arg(long, value_name = "n", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "streams", env)]
+    pub requests_per_connection: Option<u64>,
+    /// Status codes that count as a success, e.g. `2xx,304` or `200-204`
+    #[arg(long = "expect", value_name = "codes", default_value_t = StatusSet::default(), env = "EXPECTED_STATUS")]
+    pub expected_status: StatusSet,
+    /// Count responses with an expected status whose body doesn't contain this text as failed
+    /// assertions
+    #[arg(long, value_name = "text")]
+    pub assert_contains: Vec<String>,
+    /// Count responses with an expected status whose body doesn't match this regex as failed
+    /// assertions
+    #[arg(long, value_name = "regex", value_parser = is_regex_valid)]
+    pub assert_regex: Vec<Regex>,
+    /// Count responses with an expected status whose JSON body doesn't have this value at the
+    /// path as failed assertions, e.g. `$.items[0].id=7` (values that aren't JSON are strings)
+    #[arg(long, value_name = "path=value", value_parser = is_json_assertion_valid)]
+    pub assert_json: Vec<(JsonPath, Value)>,
+    /// Count responses with an expected status without this header as failed assertions
+    #[arg(long, value_name = "name", value_parser = HeaderName::from_str)]
+    pub assert_header: Vec<HeaderName>,
+    /// Count responses with an expected status and a larger body as failed assertions
+    #[arg(long, value_name = "bytes")]
+    pub max_body_size: Option<u64>,
+    /// Significant figures of the recorded latencies
+    #[arg(long, default_value_t = DEFAULT_PRECISION, value_parser = precision_in_range)]
+    pub precision: u8,
+}
+
+impl Args {
+    pub fn request_spec(&self) -> anyhow::Result<RequestSpec> {
+        let mut spec = RequestSpec::new(self.method.clone(), self.target_url.clone());
+        spec.expected_status = self.expected_status.clone();
+        spec.headers.extend(
+            self.headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+        if let Some(data) = &self.data {
+            spec.body = data.clone().into();
+        } else if let Some(path) = &self.data_file {
+            spec.body = fs::read(path)
+                .with_context(|| format!("Could not read body from {}", path.display()))?
+                .into();
+        }
+        spec.assertions = self.assertions();
+        Ok(spec)
+    }
+
+    fn assertions(&self) -> Vec<Assertion> {
+        let contains = self
+            .assert_contains
+            .iter()
+            .cloned()
+            .map(Assertion::BodyContains);
+        let regexes = self
+            .assert_regex
+            .iter()
+            .cloned()
+            .map(Assertion::BodyMatches);
+        let json = self.assert_json.iter().cloned();
+        let json = json.map(|(path, value)| Assertion::JsonEquals(path, value));
+        let headers = self
+            .assert_header
+            .iter()
+            .cloned()
+            .map(Assertion::HeaderPresent);
+        let max_body_size = self.max_body_size.map(Assertion::MaxBodySize);
+        contains
+            .chain(regexes)
+            .chain(json)
+            .chain(headers)
+            .chain(max_body_size)
+            .collect()
+    }
+
+    /// The request of the arguments, or the endpoints of `--endpoints-file` based on it.
+    pub fn request_mix(&self) -> anyhow::Result<RequestMix> {
+        let request = self.request_spec()?;
+        match &self.endpoints_file {
+            Some(path) => {
+                let content = fs::read_to_string(path)
+                    .with_context(|| format!("Could not read endpoints from {}", path.display()))?;
+                RequestMix::parse(&content, &request)
+                    .map_err(anyhow::Error::msg)
+                    .with_context(|| format!("Invalid endpoints in {}", path.display()))
+            }
+            None => Ok(request.into()),
+        }
+    }
+
+    /// The steps can use the columns of the `feeder`.
+    pub fn scenario(&self, feeder: Option<&Feeder>) -> anyhow::Result<Option<Scenario>> {
+        let Some(path) = &self.scenario_file else {
+            return Ok(None);
+        };
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read the scenario from {}", path.display()))?;
+        let columns = feeder.map_or(&[][..], Feeder::columns);
+        let scenario = Scenario::parse(&content, &self.request_spec()?, columns)
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("Invalid scenario in {}", path.display()))?;
+        Ok(Some(scenario))
+    }
+
+    pub fn feeder(&self) -> anyhow::Result<Option<Arc<Feeder>>> {
+        let Some(path) = &self.feeder_file else {
+            return Ok(None);
+        };
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read the rows from {}", path.display()))?;
+        let rows = match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => Feeder::parse_csv(&content),
+            Some("ndjson" | "jsonl") => Feeder::parse_ndjson(&content),
+            _ => Err("the file isn't `.csv` or `.ndjson`".to_string()),
+        };
+        let feeder = rows.and_then(|rows| Feeder::new(rows, self.feeder_order, self.on_feeder_end));
+        let feeder = feeder
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("Invalid rows in {}", path.display()))?;
+        Ok(Some(Arc::new(feeder)))
+    }
+
+    pub fn engine(&self) -> Engine {
+        match self.pipeline {
+            Some(_) => Engine::Raw,
+            None => self.engine,
+        }
+    }
+
+    /// Concurrent requests on every connection, either as HTTP/2 streams or pipelined.
+    pub fn streams(&self) -> u64 {
+        self.pipeline.unwrap_or(self.streams)
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        Timeouts {
+            connect: self.connect_timeout,
+            request: self.request_timeout,
+            idle_body: self.idle_timeout,
+        }
+    }
+
+    pub fn tls_options(&self) -> TlsOptions {
+        TlsOptions {
+            ca_file: self.ca_file.clone(),
+            insecure: self.insecure,
+            server_name: self.server_name.clone(),
+            client_identity: self.client_identity(),
+        }
+    }
+
+    fn client_identity(&self) -> Option<ClientIdentity> {
+        match (&self.cert_file, &self.key_file, &self.pkcs12_file) {
+            (Some(cert_file), Some(key_file), _) => Some(ClientIdentity::Pem {
+                cert_file: cert_file.clone(),
+                key_file: key_file.clone(),
+            }),
+            (_, _, Some(file)) => Some(ClientIdentity::Pkcs12 {
+                file: file.clone(),
+                password: self.pkcs12_password.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn load_profile(&self) -> anyhow::Result<Option<LoadProfile>> {
+        if let Some(path) = &self.stages_file {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Could not read stages from {}", path.display()))?;
+            let profile = LoadProfile::parse(self.num_connections, &content)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("Invalid stages in {}", path.display()))?;
+            Ok(Some(profile))
+        } else if !self.stages.is_empty() {
+            Ok(Some(LoadProfile::new(
+                self.num_connections,
+                self.stages.clone(),
+            )))
+        } else {
+            Ok(None)
+        }
+    }
 }
 
 const IN_RANGE: RangeInclusive<usize> = 1..=65535;
@@ -34,8 +361,74 @@ fn in_range(s: &str) -> Result<u64, String> {
     }
 }
 
+fn precision_in_range(s: &str) -> Result<u8, String> {
+    let precision: u8 = s.parse().map_err(|_| format!("`{s}` isn't a number"))?;
+    if PRECISION_RANGE.contains(&precision) {
+        Ok(precision)
+    } else {
+        Err(format!(
+            "precision {} not in range {}-{}",
+            s,
+            PRECISION_RANGE.start(),
+            PRECISION_RANGE.end()
+        ))
+    }
+}
+
+fn is_duration_valid(s: &str) -> Result<Duration, String> {
+    match parse_duration(s)? {
+        d if d.is_zero() => Err(format!("duration {s} must be positive")),
+        d => Ok(d),
+    }
+}
+
 fn is_url_valid(s: &str) -> Result<Uri, String> {
-    Uri::from_str(s).map_err(|uri| (format!("{s} {uri}")))
+    Uri::from_str(s).map_err(|uri| format!("{s} {uri}"))
+}
+
+fn is_rate_valid(s: &str) -> Result<f64, String> {
+    let (amount, unit) = s.split_once('/').unwrap_or((s, "s"));
+    let per_secs = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("`{s}` isn't a rate like `100/s`")),
+    };
+    let amount: f64 = amount
+        .parse()
+        .map_err(|_| format!("`{s}` isn't a rate like `100/s`"))?;
+    if amount > 0.0 {
+        Ok(amount / per_secs)
+    } else {
+        Err(format!("rate {s} must be positive"))
+    }
+}
+
+fn is_method_valid(s: &str) -> Result<Method, String> {
+    Method::from_str(&s.to_uppercase()).map_err(|method| format!("{s} {method}"))
+}
+
+fn is_header_valid(s: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or(format!("`{s}` isn't in the form `name: value`"))?;
+    let name = HeaderName::from_str(name.trim()).map_err(|e| format!("{s} {e}"))?;
+    let value = HeaderValue::from_str(value.trim()).map_err(|e| format!("{s} {e}"))?;
+    Ok((name, value))
+}
+
+fn is_regex_valid(s: &str) -> Result<Regex, String> {
+    Regex::new(s).map_err(|e| format!("`{s}` isn't a valid regex: {e}"))
+}
+
+fn is_json_assertion_valid(s: &str) -> Result<(JsonPath, Value), String> {
+    let (path, value) = s
+        .split_once('=')
+        .ok_or(format!("`{s}` isn't in the form `path=value`"))?;
+    let path = path.trim().parse()?;
+    let value = value.trim();
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::from(value));
+    Ok((path, value))
 }
 
 /*
@@ -1,10 +1,11 @@
 use std::time::Duration;
 
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser};
 use lazy_static::lazy_static;
 use prometheus::{
     histogram_opts, register_histogram_vec_with_registry, register_int_counter_vec_with_registry,
-    Encoder, HistogramVec, IntCounterVec, Registry, TextEncoder,
+    register_int_gauge_with_registry, Encoder, HistogramVec, IntCounterVec, IntGauge, Registry,
+    TextEncoder,
 };
 use tokio::{
     select,
@@ -16,7 +17,10 @@ use tokio::{
 };
 
 use client::args::Args;
-use common::agent::{self, BenchmarkParameters, RequestUpdate};
+use common::{
+    agent::{self, BenchmarkParameters, RequestUpdate},
+    Phases, RequestReport,
+};
 
 lazy_static! {
     static ref REG: Registry = Registry::new_custom(Some("loadcli".to_string()), None).unwrap();
@@ -30,10 +34,46 @@ lazy_static! {
         REG
     )
     .unwrap();
+    static ref CORRECTED_LATENCY_HIST: HistogramVec = register_histogram_vec_with_registry!(
+        histogram_opts!(
+            "corrected_latency_histogram",
+            "latency historgram of observed requests, measured from their intended send time",
+            vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0, 512.0, 1024.0, 2048.0, 4096.0]
+        ),
+        &["code"],
+        REG
+    )
+    .unwrap();
+    static ref PHASE_LATENCY_HIST: HistogramVec = register_histogram_vec_with_registry!(
+        histogram_opts!(
+            "phase_latency_histogram",
+            "latency historgram of the phases of observed requests (dns, connect, tls, ttfb, download)",
+            vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0, 512.0, 1024.0, 2048.0, 4096.0]
+        ),
+        &["phase"],
+        REG
+    )
+    .unwrap();
+    static ref BODY_BYTES_COUNTERS: IntCounterVec = register_int_counter_vec_with_registry!(
+        "body_bytes_counter",
+        "bytes of the request (sent) and response (received) bodies",
+        &["direction"],
+        REG
+    )
+    .unwrap();
     static ref REQ_COUNTERS: IntCounterVec = register_int_counter_vec_with_registry!(
         "req_counter",
-        "counter for requests",
-        &["outcome"],
+        "counter for requests, with the error kind of failures and the status code of responses",
+        &["outcome", "error", "status"],
+        REG
+    )
+    .unwrap();
+    static ref STAGE_GAUGE: IntGauge =
+        register_int_gauge_with_registry!("stage", "current stage of the load profile", REG)
+            .unwrap();
+    static ref ACTIVE_CONNECTIONS_GAUGE: IntGauge = register_int_gauge_with_registry!(
+        "active_connections",
+        "connections the load profile currently asks for",
         REG
     )
     .unwrap();
@@ -46,6 +86,18 @@ async fn main() {
     tokio::spawn(listen_metrics());
 
     let args = Args::parse();
+    if args.scenario_file.is_some() {
+        let message = "Scenarios are only supported by the cli";
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, message)
+            .exit();
+    }
+    if args.cookies {
+        let message = "Cookies are only supported by the cli";
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, message)
+            .exit();
+    }
 
     let (tx_terminate, rx_terminate) = watch::channel(true);
 
@@ -64,11 +116,22 @@ async fn main() {
 
     let bench_parameters = BenchmarkParameters {
         connections: args.num_connections,
-        target_uri: args.target_url,
+        mix: args.request_mix().expect("Invalid request:"),
+        feeder: args.feeder().expect("Invalid feeder:"),
+        seed: args.seed,
         interval_ms: args.interval_ms,
+        profile: args.load_profile().expect("Invalid load profile:"),
+        timeouts: args.timeouts(),
+        tls: args.tls_options(),
+        http_version: args.http_version,
+        streams: args.streams(),
+        engine: args.engine(),
+        requests_per_connection: args.requests_per_connection,
     };
 
-    agent::run(&bench_parameters, tx_update, rx_terminate).await;
+    agent::run(&bench_parameters, tx_update, rx_terminate)
+        .await
+        .expect("Invalid connection settings:");
 
     let _ = receive_progress_handle.await;
 }
@@ -93,8 +156,8 @@ async fn get_metrics() -> String {
 async fn receive_progress(_args: Args, mut rx: UnboundedReceiver<RequestUpdate>) {
     let print_interval = 2000;
 
-    let mut observation_count = 0;
-    let mut aggregated_latency_us = 0;
+    let mut observation_count: u128 = 0;
+    let mut aggregated_latency_us: u128 = 0;
     let mut last_observation_count = 0;
     let mut last_aggregated_latency_us = 0;
     let mut interval = interval(Duration::from_millis(print_interval));
@@ -104,9 +167,8 @@ async fn receive_progress(_args: Args, mut rx: UnboundedReceiver<RequestUpdate>)
             _ = interval.tick() => {
                 // TODO handle ticked
                 let amount = observation_count - last_observation_count;
-                if amount > 0 {
-                    let latency_sum = aggregated_latency_us - last_aggregated_latency_us;
-                    let average_latency = latency_sum / amount;
+                let latency_sum = aggregated_latency_us - last_aggregated_latency_us;
+                if let Some(average_latency) = latency_sum.checked_div(amount) {
                     log::info!("average_latency: {average_latency}us")
                 }
                 last_observation_count = observation_count;
@@ -119,20 +181,58 @@ async fn receive_progress(_args: Args, mut rx: UnboundedReceiver<RequestUpdate>)
                         RequestUpdate::Success(res) => {
                             observation_count += 1;
                             aggregated_latency_us += res.duration.as_micros();
-                            REQ_COUNTERS.with_label_values(&["Successful"]).inc();
+                            REQ_COUNTERS
+                                .with_label_values(&["Successful", "", &res.outcome.to_string()])
+                                .inc();
                             REQUEST_LATENCY_HIST
-                                .with_label_values(&[&res.status_code.to_string()])
+                                .with_label_values(&[&res.outcome.to_string()])
                                 .observe(res.duration.as_micros() as f64 / 1000.0);
+                            CORRECTED_LATENCY_HIST
+                                .with_label_values(&[&res.outcome.to_string()])
+                                .observe(res.corrected_duration.as_micros() as f64 / 1000.0);
+                            observe_phases(&res);
+                            observe_bytes(&res);
                             log::debug!("Observed request: {:?}", res);
                         }
-                        RequestUpdate::Failure => {
-                            REQ_COUNTERS.with_label_values(&["Failure"]).inc();
+                        RequestUpdate::UnexpectedStatus(res) => {
+                            REQ_COUNTERS
+                                .with_label_values(&["Unexpected", "", &res.outcome.to_string()])
+                                .inc();
+                            REQUEST_LATENCY_HIST
+                                .with_label_values(&[&res.outcome.to_string()])
+                                .observe(res.duration.as_micros() as f64 / 1000.0);
+                            CORRECTED_LATENCY_HIST
+                                .with_label_values(&[&res.outcome.to_string()])
+                                .observe(res.corrected_duration.as_micros() as f64 / 1000.0);
+                            observe_phases(&res);
+                            observe_bytes(&res);
+                            log::warn!("Observed unexpected status: {:?}", res);
+                        }
+                        RequestUpdate::AssertionFailed(res) => {
+                            REQ_COUNTERS.with_label_values(&["AssertionFailed", "", ""]).inc();
+                            REQUEST_LATENCY_HIST
+                                .with_label_values(&[&res.outcome.to_string()])
+                                .observe(res.duration.as_micros() as f64 / 1000.0);
+                            CORRECTED_LATENCY_HIST
+                                .with_label_values(&[&res.outcome.to_string()])
+                                .observe(res.corrected_duration.as_micros() as f64 / 1000.0);
+                            observe_phases(&res);
+                            observe_bytes(&res);
+                            log::warn!("Observed failed assertion: {:?}", res);
+                        }
+                        RequestUpdate::Failure(kind) => {
+                            REQ_COUNTERS.with_label_values(&["Failure", kind.as_str(), ""]).inc();
                             log::warn!("Observed failure: {:?}", update);
                         }
                         RequestUpdate::Timeout => {
-                            REQ_COUNTERS.with_label_values(&["Timeout"]).inc();
+                            REQ_COUNTERS.with_label_values(&["Timeout", "", ""]).inc();
                             log::warn!("Observed timeout: {:?}", update);
                         }
+                        RequestUpdate::Stage(state) => {
+                            STAGE_GAUGE.set(state.stage as i64 + 1);
+                            ACTIVE_CONNECTIONS_GAUGE.set(state.connections as i64);
+                            log::info!("Load profile at {:?}", state);
+                        }
                     },
                     None => {
                         log::info!("Terminating printer");
@@ -143,3 +243,22 @@ async fn receive_progress(_args: Args, mut rx: UnboundedReceiver<RequestUpdate>)
         }
     }
 }
+
+fn observe_phases(report: &RequestReport) {
+    for (name, phase) in Phases::NAMES.iter().zip(report.phases.to_array()) {
+        if let Some(phase) = phase {
+            PHASE_LATENCY_HIST
+                .with_label_values(&[name])
+                .observe(phase.as_micros() as f64 / 1000.0);
+        }
+    }
+}
+
+fn observe_bytes(report: &RequestReport) {
+    BODY_BYTES_COUNTERS
+        .with_label_values(&["sent"])
+        .inc_by(report.sent_bytes);
+    BODY_BYTES_COUNTERS
+        .with_label_values(&["received"])
+        .inc_by(report.received_bytes);
+}
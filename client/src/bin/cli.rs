@@ -1,17 +1,21 @@
+use std::collections::HashMap;
 use std::fs::OpenOptions;
+use std::path::PathBuf;
 use std::time::Duration;
-use std::{collections::HashMap, path::PathBuf};
 
 use anyhow::Context;
 use clap::Parser;
 use indicatif::{ProgressBar, ProgressStyle};
-use statrs::statistics::Data;
 use tabled::Table;
 use tokio::sync::mpsc::{self, error::TryRecvError, UnboundedReceiver};
 
 use client::args::Args;
 use client::table::ResultTableEntry;
-use common::cli::{self, BenchmarkParameters, BenchmarkReport, BenchmarkUpdate};
+use common::{
+    cli::{self, BenchmarkParameters, BenchmarkReport, BenchmarkUpdate, RateParameters},
+    profile::LoadProfile,
+    Engine, HttpVersion,
+};
 
 pub const _DEFAULT_URL: &str = "http://127.0.0.1:8080/person";
 
@@ -21,14 +25,39 @@ async fn main() {
 
     let (tx, rx) = mpsc::unbounded_channel::<BenchmarkUpdate>();
 
-    let display_progress = tokio::spawn(display_progress(args.clone(), rx));
+    let profile = args.load_profile().expect("Invalid load profile:");
+    let feeder = args.feeder().expect("Invalid feeder:");
+    let run_duration = profile
+        .as_ref()
+        .map(LoadProfile::total_duration)
+        .or(args.duration);
+
+    let display_progress = tokio::spawn(display_progress(args.clone(), run_duration, rx));
 
     println!("Running on {} ...", &args.target_url);
 
     let params = BenchmarkParameters {
         connections: args.num_connections,
         requests: args.num_requests,
-        target_uri: args.target_url,
+        mix: args.request_mix().expect("Invalid request:"),
+        scenario: args.scenario(feeder.as_deref()).expect("Invalid scenario:"),
+        cookies: args.cookies,
+        feeder,
+        seed: args.seed,
+        rate: args.rate.map(|requests_per_sec| RateParameters {
+            requests_per_sec,
+            overflow: args.on_full,
+        }),
+        duration: args.duration,
+        profile,
+        precision: args.precision,
+        max_errors: args.max_errors,
+        timeouts: args.timeouts(),
+        tls: args.tls_options(),
+        http_version: args.http_version,
+        streams: args.streams(),
+        engine: args.engine(),
+        requests_per_connection: args.requests_per_connection,
     };
 
     let benchmark_report = cli::run(&params, tx).await.expect("The benchmark failed:");
@@ -36,7 +65,7 @@ async fn main() {
     let _ = display_progress.await;
 
     print_summary(&params, &benchmark_report);
-    let data = calc_tabular_data(&benchmark_report);
+    let data = calc_tabular_data(&params, &benchmark_report);
     print_details(&data);
 
     if let Some(output_file) = args.output_file {
@@ -46,43 +75,235 @@ async fn main() {
 
 fn print_summary(params: &BenchmarkParameters, benchmark_report: &BenchmarkReport) {
     let BenchmarkReport {
+        sent_requests,
         ok_requests,
         failed_requests,
+        error_requests,
+        timeout_requests,
+        goaway_requests,
+        reset_requests,
+        pipeline_closed_requests,
+        assertion_failed_requests,
+        connect_times,
+        sent_bytes,
+        received_bytes,
+        errors,
+        aborted,
         total_duration_ms,
+        delayed_requests,
+        dropped_requests,
+        transactions,
+        failed_transactions,
+        established_sessions,
+        lost_sessions,
         ..
     } = benchmark_report;
 
     let BenchmarkParameters {
         connections,
-        requests,
-        target_uri,
+        mix,
+        scenario,
+        cookies,
+        rate,
+        profile,
+        http_version,
+        streams,
+        engine,
+        ..
     } = params;
 
+    let connections = profile
+        .as_ref()
+        .map(LoadProfile::max_connections)
+        .unwrap_or(*connections);
+
+    if let Some(scenario) = scenario {
+        println!(
+            "Sent {} requests in {}ms from {} virtual users running {} steps",
+            sent_requests,
+            total_duration_ms,
+            connections,
+            scenario.steps.len()
+        );
+        for (i, step) in scenario.steps.iter().enumerate() {
+            let variables = step.extractors.iter().map(|e| e.variable.as_str());
+            let variables = variables.collect::<Vec<_>>().join(", ");
+            let extracting = match variables.is_empty() {
+                true => String::new(),
+                false => format!(", extracting {variables}"),
+            };
+            println!(
+                "  {}. {}: {}, expecting {}{extracting}, then thinking for {:?}",
+                i + 1,
+                step.name,
+                step.request,
+                step.request.expected_status(),
+                step.think_time
+            );
+        }
+        println!(
+            "Performed {ok_requests} ({failed_requests} failed, {error_requests} errors, {timeout_requests} timeouts) requests."
+        );
+        let iterations = transactions.values().map(|h| h.duration.len()).sum::<u64>();
+        println!(
+            "Completed {} of {iterations} iterations, {failed_transactions} stopped at a step that failed.",
+            iterations - failed_transactions
+        );
+    } else if let [endpoint] = mix.endpoints() {
+        let request = &endpoint.request;
+        println!(
+            "Sent {} {} requests in {}ms to {} from {} connections",
+            sent_requests, request.method, total_duration_ms, request.uri, connections
+        );
+        println!(
+            "Performed {ok_requests} ({failed_requests} failed, {error_requests} errors, {timeout_requests} timeouts) requests, expecting {}.",
+            request.expected_status
+        );
+    } else {
+        println!(
+            "Sent {} requests in {}ms to {} endpoints from {} connections",
+            sent_requests,
+            total_duration_ms,
+            mix.endpoints().len(),
+            connections
+        );
+        for endpoint in mix.endpoints() {
+            let request = &endpoint.request;
+            println!(
+                "  {} (weight {}): {} {}, expecting {}",
+                endpoint.name,
+                endpoint.weight,
+                request.method,
+                request.uri,
+                request.expected_status
+            );
+        }
+        println!(
+            "Performed {ok_requests} ({failed_requests} failed, {error_requests} errors, {timeout_requests} timeouts) requests."
+        );
+    }
+    let assertions = &mix.endpoints()[0].request.assertions;
+    if !assertions.is_empty() {
+        let assertions = assertions.iter().map(ToString::to_string);
+        println!(
+            "{assertion_failed_requests} responses failed the assertions: {}.",
+            assertions.collect::<Vec<_>>().join(", ")
+        );
+    }
+    if *cookies {
+        println!(
+            "Kept cookies per connection: {established_sessions} sessions established, {lost_sessions} lost."
+        );
+    }
+    let seconds = (*total_duration_ms).max(1) as f64 / 1000.0;
     println!(
-        "Sent {} requests in {}ms to {} from {} connections",
-        requests, total_duration_ms, target_uri, connections
+        "Transferred {sent_bytes} bytes of request bodies ({:.2} bytes/s) and {received_bytes} bytes of response bodies ({:.2} bytes/s).",
+        *sent_bytes as f64 / seconds,
+        *received_bytes as f64 / seconds
     );
-    println!("Performed {ok_requests} ({failed_requests} failed) requests.");
+    let mut errors = errors.iter().collect::<Vec<_>>();
+    errors.sort();
+    for (kind, count) in errors {
+        println!("  {kind}: {count}");
+    }
+    if *http_version == HttpVersion::Http2 {
+        println!(
+            "HTTP/2 with {streams} streams per connection: {goaway_requests} requests failed by GOAWAY, {reset_requests} by RST_STREAM."
+        );
+    }
+    if *engine == Engine::Raw && *streams > 1 {
+        println!(
+            "HTTP/1.1 pipelining {streams} requests per connection: {pipeline_closed_requests} requests lost to connections closed mid-pipeline."
+        );
+    }
+    if !connect_times.is_empty() {
+        println!(
+            "Opened {} new connections ({:.2}/s), connecting took {:.2}us on average, {}us at p99.",
+            connect_times.len(),
+            connect_times.len() as f64 * 1000.0 / (*total_duration_ms).max(1) as f64,
+            connect_times.mean(),
+            connect_times.value_at_quantile(0.99)
+        );
+    }
+    if *aborted {
+        println!("Aborted early because of too many errors.");
+    }
+    if let Some(profile) = profile {
+        let mut from = profile.start;
+        for (i, stage) in profile.stages.iter().enumerate() {
+            println!(
+                "Stage {}: {} to {} connections in {:?}",
+                i + 1,
+                from,
+                stage.target,
+                stage.duration
+            );
+            from = stage.target;
+        }
+    }
+    if let Some(rate) = rate {
+        println!(
+            "Scheduled at {:.2} requests/s: {delayed_requests} delayed, {dropped_requests} dropped (all connections busy).",
+            rate.requests_per_sec
+        );
+    }
 }
 
-fn calc_tabular_data(benchmark_report: &BenchmarkReport) -> Vec<ResultTableEntry> {
-    let mut microseconds_by_status_code: HashMap<u16, Vec<f64>> = HashMap::new();
+fn calc_tabular_data(
+    params: &BenchmarkParameters,
+    benchmark_report: &BenchmarkReport,
+) -> Vec<ResultTableEntry> {
+    let stage_durations = params.profile.iter().flat_map(|p| &p.stages);
+    let stages = benchmark_report
+        .stages
+        .iter()
+        .zip(stage_durations)
+        .enumerate()
+        .map(|(i, (histograms, stage))| ((i + 1).to_string(), histograms, stage.duration));
 
-    benchmark_report
-        .reports
+    let total_duration = Duration::from_millis(benchmark_report.total_duration_ms);
+    let stages = std::iter::once((
+        "all".to_string(),
+        &benchmark_report.histograms,
+        total_duration,
+    ))
+    .chain(stages)
+    .map(|(stage, by_outcome, elapsed)| (stage, "all".to_string(), by_outcome, elapsed));
+    let names = match &params.scenario {
+        Some(scenario) => scenario.steps.iter().map(|s| s.name.clone()).collect(),
+        None => params
+            .mix
+            .endpoints()
+            .iter()
+            .map(|e| e.name.clone())
+            .collect(),
+    };
+    let endpoints = benchmark_report
+        .endpoints
         .iter()
-        .flat_map(|s| &s.requests)
-        .for_each(|r| {
-            microseconds_by_status_code
-                .entry(r.status_code)
-                .or_default()
-                .push(r.duration.as_micros() as f64)
-        });
-
-    microseconds_by_status_code
-        .into_iter()
-        .map(|(k, v)| (k, Data::new(v)))
-        .map(|(k, duration)| ResultTableEntry::new(k, duration))
+        .zip::<Vec<_>>(names)
+        .map(|(by_outcome, name)| ("all".to_string(), name, by_outcome, total_duration));
+    let transactions = (!benchmark_report.transactions.is_empty()).then(|| {
+        let transactions = &benchmark_report.transactions;
+        (
+            "all".to_string(),
+            "transaction".to_string(),
+            transactions,
+            total_duration,
+        )
+    });
+
+    stages
+        .chain(endpoints)
+        .chain(transactions)
+        .flat_map(|(stage, endpoint, by_outcome, elapsed)| {
+            let mut by_outcome = by_outcome.iter().collect::<Vec<_>>();
+            by_outcome.sort_by_key(|(outcome, _)| **outcome);
+            by_outcome.into_iter().map(move |(outcome, histograms)| {
+                let (stage, endpoint) = (stage.clone(), endpoint.clone());
+                ResultTableEntry::new(stage, endpoint, *outcome, histograms, elapsed)
+            })
+        })
         .collect::<Vec<ResultTableEntry>>()
 }
 
@@ -91,24 +312,44 @@ fn print_details(data: &Vec<ResultTableEntry>) {
     println!("{}", t);
 }
 
-async fn display_progress(args: Args, mut rx: UnboundedReceiver<BenchmarkUpdate>) {
-    let pbar = ProgressBar::new(args.num_requests);
-    pbar.set_style(
-        ProgressStyle::with_template(
+async fn display_progress(
+    args: Args,
+    run_duration: Option<Duration>,
+    mut rx: UnboundedReceiver<BenchmarkUpdate>,
+) {
+    let (length, template) = match run_duration {
+        Some(duration) => (
+            duration.as_secs(),
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len}s",
+        ),
+        None => (
+            args.num_requests,
             "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {per_sec:7}",
-        )
-        .unwrap()
-        .progress_chars("#>-"),
+        ),
+    };
+    let pbar = ProgressBar::new(length);
+    pbar.set_style(
+        ProgressStyle::with_template(template)
+            .unwrap()
+            .progress_chars("#>-"),
     );
     pbar.enable_steady_tick(Duration::from_millis(100));
-    let mut requests_per_connections = vec![0u64; args.num_connections as usize];
+    let mut requests_per_connections = HashMap::new();
     loop {
         match rx.try_recv() {
             Ok(update) => {
-                requests_per_connections[update.connection_id as usize] = update.current_request;
-                pbar.set_position(requests_per_connections.iter().sum())
+                requests_per_connections.insert(
+                    (update.connection_id, update.stream_id),
+                    update.current_request,
+                );
+                if run_duration.is_none() {
+                    pbar.set_position(requests_per_connections.values().sum())
+                }
             }
             Err(TryRecvError::Empty) => {
+                if run_duration.is_some() {
+                    pbar.set_position(pbar.elapsed().as_secs().min(length));
+                }
                 pbar.tick();
                 tokio::time::sleep(Duration::from_millis(50)).await
             }
@@ -139,9 +380,12 @@ fn write_csv(path: &PathBuf, data: &Vec<ResultTableEntry>) -> anyhow::Result<()>
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use clap::Parser;
 
     use client::args::Args;
+    use common::{tls::ClientIdentity, Engine, HttpVersion};
 
     #[test]
     fn test_works_with_url() {
@@ -163,4 +407,221 @@ mod tests {
         let args = Args::try_parse_from(["loadcli", "invalid_url//"]);
         assert!(args.is_err());
     }
+
+    #[test]
+    fn test_request_spec_from_args() {
+        let args = Args::try_parse_from([
+            "loadcli",
+            "http://127.0.0.1:8080/person",
+            "-X",
+            "post",
+            "-H",
+            "content-type: application/json",
+            "-d",
+            "{}",
+        ])
+        .unwrap();
+        let spec = args.request_spec().unwrap();
+        assert_eq!(spec.method, "POST");
+        assert_eq!(spec.headers["content-type"], "application/json");
+        assert_eq!(spec.body, "{}");
+    }
+
+    #[test]
+    fn test_assertion_arguments() {
+        let args = Args::try_parse_from([
+            "loadcli",
+            "http://dummy",
+            "--assert-contains",
+            "ok",
+            "--assert-json",
+            "$.items[0].id=7",
+            "--assert-json",
+            "$.status=ok",
+            "--assert-header",
+            "etag",
+            "--max-body-size",
+            "1024",
+        ])
+        .unwrap();
+        let assertions = args.request_spec().unwrap().assertions;
+        let assertions = assertions
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            assertions,
+            [
+                "body contains `ok`",
+                "`$.items[0].id` equals `7`",
+                "`$.status` equals `\"ok\"`",
+                "header `etag` is present",
+                "body has at most 1024 bytes",
+            ]
+        );
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "--assert-regex", "("]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_rate_argument() {
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "--rate", "600/m"]).unwrap();
+        assert_eq!(args.rate, Some(10.0));
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "--rate", "0/s"]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_invalid_precision_argument() {
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "--precision", "6"]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_duration_argument() {
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "--duration", "10m"]).unwrap();
+        assert_eq!(args.duration, Some(Duration::from_secs(600)));
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "--duration", "10x"]);
+        assert!(args.is_err());
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "-r", "1", "--duration", "1s"]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_stages_argument() {
+        let args = Args::try_parse_from([
+            "loadcli",
+            "http://dummy",
+            "-c",
+            "10",
+            "--stages",
+            "2m:500,10m:500,1m:0",
+        ])
+        .unwrap();
+        let profile = args.load_profile().unwrap().unwrap();
+        assert_eq!(profile.start, 10);
+        assert_eq!(profile.stages.len(), 3);
+        assert_eq!(profile.total_duration(), Duration::from_secs(780));
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "--stages", "2m"]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_expect_argument() {
+        let args =
+            Args::try_parse_from(["loadcli", "http://dummy", "--expect", "2xx,404"]).unwrap();
+        let spec = args.request_spec().unwrap();
+        assert!(spec.expected_status.contains(404));
+        assert!(!spec.expected_status.contains(301));
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "--expect", "ok"]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_tls_arguments() {
+        let args = Args::try_parse_from([
+            "loadcli",
+            "https://127.0.0.1:8443/",
+            "-k",
+            "--sni",
+            "staging.example.com",
+        ])
+        .unwrap();
+        let tls = args.tls_options();
+        assert!(tls.insecure);
+        assert_eq!(tls.server_name.as_deref(), Some("staging.example.com"));
+        assert!(tls.ca_file.is_none());
+        assert!(tls.client_identity.is_none());
+    }
+
+    #[test]
+    fn test_client_certificate_arguments() {
+        let args = Args::try_parse_from([
+            "loadcli",
+            "https://dummy",
+            "--p12",
+            "client.p12",
+            "--p12-password",
+            "secret",
+        ])
+        .unwrap();
+        assert!(matches!(
+            args.tls_options().client_identity,
+            Some(ClientIdentity::Pkcs12 { password, .. }) if password == "secret"
+        ));
+        let args = Args::try_parse_from(["loadcli", "https://dummy", "--cert", "client.pem"]);
+        assert!(args.is_err());
+        let args = Args::try_parse_from([
+            "loadcli",
+            "https://dummy",
+            "--cert",
+            "client.pem",
+            "--key",
+            "client.key",
+            "--p12",
+            "client.p12",
+        ]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_http2_arguments() {
+        let args = Args::try_parse_from([
+            "loadcli",
+            "http://dummy",
+            "--http-version",
+            "2",
+            "--streams",
+            "100",
+        ])
+        .unwrap();
+        assert_eq!(args.http_version, HttpVersion::Http2);
+        assert_eq!(args.streams, 100);
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "--http-version", "3"]);
+        assert!(args.is_err());
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "--streams", "0"]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_engine_arguments() {
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "--pipeline", "16"]).unwrap();
+        assert_eq!(args.engine(), Engine::Raw);
+        assert_eq!(args.streams(), 16);
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "--engine", "raw"]).unwrap();
+        assert_eq!(args.engine(), Engine::Raw);
+        assert_eq!(args.streams(), 1);
+        let args = Args::try_parse_from([
+            "loadcli",
+            "http://dummy",
+            "--pipeline",
+            "16",
+            "--streams",
+            "2",
+        ]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn test_requests_per_connection_argument() {
+        let args = Args::try_parse_from(["loadcli", "http://dummy"]).unwrap();
+        assert_eq!(args.requests_per_connection, None);
+        let args =
+            Args::try_parse_from(["loadcli", "http://dummy", "--requests-per-connection", "1"])
+                .unwrap();
+        assert_eq!(args.requests_per_connection, Some(1));
+        for invalid in [
+            &["--requests-per-connection", "0"][..],
+            &["--requests-per-connection", "5", "--streams", "2"],
+        ] {
+            let args = Args::try_parse_from(["loadcli", "http://dummy"].iter().chain(invalid));
+            assert!(args.is_err());
+        }
+    }
+
+    #[test]
+    fn test_invalid_header_argument() {
+        let args = Args::try_parse_from(["loadcli", "http://dummy", "-H", "no-colon"]);
+        assert!(args.is_err());
+    }
 }
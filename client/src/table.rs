@@ -1,12 +1,20 @@
+use std::time::Duration;
+
 use serde::Serialize;
-use statrs::statistics::{Data, Distribution, Max, Min, OrderStatistics};
 use tabled::{self, Tabled};
 
+use common::{histogram::RequestHistograms, Outcome, Phase};
+
 const MICROS_PER_SEC: f64 = 1_000_000.0;
 
 #[derive(Tabled, Serialize)]
 pub struct ResultTableEntry {
-    pub status_code: u16,
+    pub stage: String,
+    /// The endpoint of the request mix or step of the scenario, `transaction` for whole iterations
+    /// of the scenario, or `all`.
+    pub endpoint: String,
+    /// The status code, or the kind of error if there was no response.
+    pub outcome: String,
     pub observations: u32,
     #[tabled(display_with = "two_digit_float")]
     pub average_rate: f64,
@@ -21,21 +29,96 @@ pub struct ResultTableEntry {
     #[tabled(display_with = "two_digit_float")]
     p90: f64,
     #[tabled(display_with = "two_digit_float")]
+    p90_corrected: f64,
+    #[tabled(display_with = "two_digit_float")]
     p99: f64,
+    #[tabled(display_with = "two_digit_float")]
+    p99_corrected: f64,
+    /// Bytes of the request and response bodies, in total and per second of the run or stage.
+    sent_bytes: u64,
+    received_bytes: u64,
+    #[tabled(display_with = "two_digit_float")]
+    sent_bytes_per_sec: f64,
+    #[tabled(display_with = "two_digit_float")]
+    received_bytes_per_sec: f64,
+    /// Requests that opened a new connection.
+    new_connections: u32,
+    // the phases of the requests they were timed for
+    #[tabled(display_with = "two_digit_float")]
+    dns_p50: f64,
+    #[tabled(display_with = "two_digit_float")]
+    dns_p99: f64,
+    #[tabled(display_with = "two_digit_float")]
+    connect_p50: f64,
+    #[tabled(display_with = "two_digit_float")]
+    connect_p99: f64,
+    #[tabled(display_with = "two_digit_float")]
+    tls_p50: f64,
+    #[tabled(display_with = "two_digit_float")]
+    tls_p99: f64,
+    #[tabled(display_with = "two_digit_float")]
+    ttfb_p50: f64,
+    #[tabled(display_with = "two_digit_float")]
+    ttfb_p99: f64,
+    #[tabled(display_with = "two_digit_float")]
+    download_p50: f64,
+    #[tabled(display_with = "two_digit_float")]
+    download_p99: f64,
 }
 
 impl ResultTableEntry {
-    pub fn new(status_code: u16, mut data: Data<Vec<f64>>) -> Self {
+    /// `elapsed` is the duration of the run or stage, for the bandwidth.
+    pub fn new(
+        stage: String,
+        endpoint: String,
+        outcome: Outcome,
+        histograms: &RequestHistograms,
+        elapsed: Duration,
+    ) -> Self {
+        let RequestHistograms {
+            duration,
+            corrected_duration,
+            sent_bytes,
+            received_bytes,
+            ..
+        } = histograms;
+        let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+        // phases no request timed are shown as 0
+        let quantile = |phase, quantile| {
+            let phase = histograms.phase(phase);
+            phase.map_or(0.0, |h| h.value_at_quantile(quantile) as f64)
+        };
         ResultTableEntry {
-            status_code,
-            observations: data.len() as u32,
-            average_rate: MICROS_PER_SEC / data.mean().unwrap_or_default(),
-            mean: data.mean().unwrap_or_default(),
-            min: data.min(),
-            max: data.max(),
-            sd: data.std_dev().unwrap_or_default(),
-            p90: data.percentile(90),
-            p99: data.percentile(99),
+            stage,
+            endpoint,
+            outcome: outcome.to_string(),
+            observations: duration.len() as u32,
+            average_rate: MICROS_PER_SEC / duration.mean(),
+            mean: duration.mean(),
+            min: duration.min() as f64,
+            max: duration.max() as f64,
+            sd: duration.stdev(),
+            p90: duration.value_at_quantile(0.90) as f64,
+            p90_corrected: corrected_duration.value_at_quantile(0.90) as f64,
+            p99: duration.value_at_quantile(0.99) as f64,
+            p99_corrected: corrected_duration.value_at_quantile(0.99) as f64,
+            sent_bytes: *sent_bytes,
+            received_bytes: *received_bytes,
+            sent_bytes_per_sec: *sent_bytes as f64 / seconds,
+            received_bytes_per_sec: *received_bytes as f64 / seconds,
+            new_connections: histograms
+                .phase(Phase::Connect)
+                .map_or(0, |h| h.len() as u32),
+            dns_p50: quantile(Phase::Dns, 0.50),
+            dns_p99: quantile(Phase::Dns, 0.99),
+            connect_p50: quantile(Phase::Connect, 0.50),
+            connect_p99: quantile(Phase::Connect, 0.99),
+            tls_p50: quantile(Phase::Tls, 0.50),
+            tls_p99: quantile(Phase::Tls, 0.99),
+            ttfb_p50: quantile(Phase::Ttfb, 0.50),
+            ttfb_p99: quantile(Phase::Ttfb, 0.99),
+            download_p50: quantile(Phase::Download, 0.50),
+            download_p99: quantile(Phase::Download, 0.99),
         }
     }
 }
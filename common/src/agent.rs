@@ -1,102 +1,258 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use hyper::{Client, Uri};
 use tokio::{
     select,
     sync::{mpsc, watch},
-    time::{interval, timeout, Instant},
+    time::{interval, Instant},
 };
 
-use crate::{RequestReport, StatusOnlyHttpClient};
+use crate::{
+    feeder::Feeder,
+    generator,
+    mix::RequestMix,
+    profile::{LoadProfile, ProfileState},
+    template::Variables,
+    BenchmarkClient, Engine, ErrorKind, HttpVersion, Outcome, RequestReport, RequestSender,
+    RequestSpec, Timeouts, TlsOptions,
+};
 
+/// Used when no request timeout is configured.
 const REQ_TIMEOUT: u64 = 500;
 
 #[derive(Clone)]
 pub struct BenchmarkParameters {
     pub connections: u64,
-    pub target_uri: Uri,
+    /// The endpoints the requests are spread over.
+    pub mix: RequestMix,
+    /// Rows of test data for the `{{column}}` placeholders of the requests.
+    pub feeder: Option<Arc<Feeder>>,
+    /// Makes the random values of the connections repeat, see `cli::BenchmarkParameters::seed`.
+    pub seed: Option<u64>,
     pub interval_ms: u64,
+    /// Vary the number of active connections over time instead of using `connections` throughout.
+    /// The last stage's target is kept once the profile is over.
+    pub profile: Option<LoadProfile>,
+    pub timeouts: Timeouts,
+    /// Only used for `https` targets.
+    pub tls: TlsOptions,
+    pub http_version: HttpVersion,
+    /// Requests sent concurrently on every connection, each stream at `interval_ms`.
+    pub streams: u64,
+    pub engine: Engine,
+    /// Open a new connection after this many requests, instead of keeping it alive.
+    pub requests_per_connection: Option<u64>,
 }
 
 struct ConnectionParameters {
     pub connection_id: u64,
-    pub target_uri: Uri,
+    pub mix: RequestMix,
+    pub feeder: Option<Arc<Feeder>>,
+    pub seed: Option<u64>,
     pub interval_ms: u64,
+    pub profile: Option<watch::Receiver<ProfileState>>,
 }
 
 #[derive(Debug)]
 pub enum RequestUpdate {
     Success(RequestReport),
-    Failure,
+    /// A response with a status code that isn't expected.
+    UnexpectedStatus(RequestReport),
+    /// A response with an expected status that failed an assertion of the request.
+    AssertionFailed(RequestReport),
+    Failure(ErrorKind),
     Timeout,
+    /// The load profile moved on.
+    Stage(ProfileState),
 }
 
 pub async fn run(
     params: &BenchmarkParameters,
     tx_update: mpsc::UnboundedSender<RequestUpdate>,
     rx_terminate: watch::Receiver<bool>,
-) {
+) -> anyhow::Result<()> {
     let BenchmarkParameters {
         connections,
-        target_uri,
+        mix,
+        feeder,
+        seed,
         interval_ms,
+        profile,
+        timeouts,
+        tls,
+        http_version,
+        streams,
+        engine,
+        requests_per_connection,
     } = params;
 
-    let _handles = (0..*connections)
-        .map(|id| {
+    let timeouts = Timeouts {
+        request: timeouts
+            .request
+            .or(Some(Duration::from_millis(REQ_TIMEOUT))),
+        ..*timeouts
+    };
+
+    Feeder::check_columns(feeder.as_deref(), mix.variables()).map_err(anyhow::Error::msg)?;
+    let tls = tls.config_for(mix.tls_uri())?;
+
+    let connections = match profile {
+        Some(profile) => profile.max_connections(),
+        None => *connections,
+    };
+    let rx_profile = profile
+        .clone()
+        .map(|profile| drive_profile(profile, tx_update.clone()));
+
+    let clients = (0..connections)
+        .map(|_| {
+            let tls = tls.as_ref();
+            BenchmarkClient::new(
+                *engine,
+                timeouts,
+                tls,
+                *http_version,
+                *requests_per_connection,
+            )
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let _handles = (0..connections)
+        .zip(clients)
+        .flat_map(|(id, client)| (0..(*streams).max(1)).map(move |_| (id, client.clone())))
+        .enumerate()
+        .map(|(lane, (id, client))| {
             let params = ConnectionParameters {
                 connection_id: id,
-                target_uri: target_uri.clone(),
+                mix: mix.clone(),
+                feeder: feeder.clone(),
+                seed: seed.map(|seed| seed.wrapping_add(lane as u64)),
                 interval_ms: *interval_ms,
+                profile: rx_profile.clone(),
             };
             tokio::spawn(connection_task(
-                Client::builder().build_http(),
+                client,
                 params,
                 tx_update.clone(),
                 rx_terminate.clone(),
             ))
         })
         .collect::<Vec<_>>();
+
+    Ok(())
+}
+
+/// Runs the load profile in the background and reports every new stage as an update.
+fn drive_profile(
+    profile: LoadProfile,
+    tx_update: mpsc::UnboundedSender<RequestUpdate>,
+) -> watch::Receiver<ProfileState> {
+    let (tx_profile, rx_profile) = watch::channel(profile.initial_state());
+    let _ = tx_update.send(RequestUpdate::Stage(profile.initial_state()));
+
+    let mut rx_stage = rx_profile.clone();
+    tokio::spawn(async move {
+        while rx_stage.changed().await.is_ok() {
+            let state = *rx_stage.borrow();
+            if tx_update.send(RequestUpdate::Stage(state)).is_err() {
+                break;
+            }
+        }
+    });
+    tokio::spawn(async move { profile.drive(Instant::now(), tx_profile).await });
+
+    rx_profile
 }
 
 async fn connection_task(
-    client: impl StatusOnlyHttpClient,
-    params: ConnectionParameters,
+    client: impl RequestSender,
+    mut params: ConnectionParameters,
     tx_update: mpsc::UnboundedSender<RequestUpdate>,
     mut rx_terminate: watch::Receiver<bool>,
 ) {
+    let id = params.connection_id;
     let mut interval = interval(Duration::from_millis(params.interval_ms));
+    let mut variables = Variables::new();
+    let mut first = true;
+    let mut rng = generator::rng(params.seed);
     while let Ok(false) = rx_terminate.has_changed() {
-        do_request(&client, &params.target_uri, &tx_update).await;
-        select! {
-            _ = interval.tick() => {
-                // just continue
+        if let Some(rx_profile) = &mut params.profile {
+            if rx_profile.borrow().connections <= id {
+                select! {
+                    active = rx_profile.wait_for(|s| s.connections > id) => {
+                        if active.is_err() {
+                            break;
+                        }
+                    }
+                    _ = rx_terminate.changed() => {
+                        break;
+                    }
+                };
+                // don't catch up on the ticks missed while inactive
+                interval = tokio::time::interval(Duration::from_millis(params.interval_ms));
             }
+        }
+        let intended_start = select! {
+            intended_start = interval.tick() => intended_start,
             _ = rx_terminate.changed() => {
                 break;
             }
         };
+        if let Some(feeder) = &params.feeder {
+            if !feeder.feed(&mut variables, first, &mut rng) {
+                log::info!("Connection {id} ran out of rows");
+                break;
+            }
+        }
+        first = false;
+        let (_, request) = params.mix.pick(&mut rng);
+        match request.render(&variables, &mut rng) {
+            Ok(request) => do_request(&client, &request, intended_start, &tx_update).await,
+            Err(e) => {
+                log::warn!("A request can't be built (connection {id}): {e}");
+                let _ = tx_update.send(RequestUpdate::Failure(ErrorKind::Other));
+            }
+        }
     }
     log::info!("Terminating connection {}", params.connection_id);
 }
 
 pub async fn do_request(
-    client: &impl StatusOnlyHttpClient,
-    target_uri: &Uri,
+    client: &impl RequestSender,
+    request: &RequestSpec,
+    intended_start: Instant,
     tx_update: &mpsc::UnboundedSender<RequestUpdate>,
 ) {
-    let request_future = client.get(target_uri.clone());
     let start_instant = Instant::now();
-    let result = timeout(Duration::from_millis(REQ_TIMEOUT), request_future).await;
-    let duration = Instant::now().duration_since(start_instant);
-
-    let request_update = match result {
-        Ok(Ok(status_code)) => RequestUpdate::Success(RequestReport {
-            status_code,
-            duration,
-        }),
-        Ok(Err(_)) => RequestUpdate::Failure,
-        Err(_) => RequestUpdate::Timeout,
+    let (result, stats) = client.send_with_stats(request).await;
+    let start_instant = stats.written.unwrap_or(start_instant);
+    let end_instant = Instant::now();
+    let duration = end_instant.duration_since(start_instant);
+    let corrected_duration = end_instant.duration_since(intended_start);
+
+    let report = |outcome| RequestReport {
+        outcome,
+        duration,
+        corrected_duration,
+        phases: stats.phases,
+        sent_bytes: request.body.len() as u64,
+        received_bytes: stats.received_bytes,
+        headers: None,
+        body: None,
+    };
+    let request_update = match (result, &stats.failed_assertion) {
+        (Ok(_), Some(_)) => RequestUpdate::AssertionFailed(report(Outcome::AssertionFailed)),
+        (Ok(status_code), None) => {
+            let report = report(Outcome::Status(status_code));
+            if request.expected_status.contains(status_code) {
+                RequestUpdate::Success(report)
+            } else {
+                RequestUpdate::UnexpectedStatus(report)
+            }
+        }
+        (Err(e), _) => match ErrorKind::classify(&e) {
+            ErrorKind::Timeout => RequestUpdate::Timeout,
+            kind => RequestUpdate::Failure(kind),
+        },
     };
 
     // TODO fix unwrap
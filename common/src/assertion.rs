@@ -0,0 +1,124 @@
+use std::fmt;
+
+use hyper::header::{HeaderMap, HeaderName};
+use regex::bytes::Regex;
+use serde_json::Value;
+
+use crate::{error::AssertionError, json_path::JsonPath};
+
+/// A check of a response beyond its status code.
+#[derive(Debug, Clone)]
+pub enum Assertion {
+    /// The body contains the text.
+    BodyContains(String),
+    /// The body matches the regular expression.
+    BodyMatches(Regex),
+    /// The body is JSON with this value at the path.
+    JsonEquals(JsonPath, Value),
+    /// The response has the header.
+    HeaderPresent(HeaderName),
+    /// The body has at most this many bytes.
+    MaxBodySize(u64),
+}
+
+impl Assertion {
+    /// Whether the assertion looks at the content of the body, which is discarded otherwise.
+    pub fn needs_body(&self) -> bool {
+        matches!(
+            self,
+            Assertion::BodyContains(_) | Assertion::BodyMatches(_) | Assertion::JsonEquals(..)
+        )
+    }
+
+    /// `body` is only complete if `needs_body`, `body_size` always is.
+    pub fn check(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+        body_size: u64,
+    ) -> Result<(), AssertionError> {
+        let holds = match self {
+            Assertion::BodyContains(text) => {
+                let text = text.as_bytes();
+                text.is_empty() || body.windows(text.len()).any(|w| w == text)
+            }
+            Assertion::BodyMatches(regex) => regex.is_match(body),
+            Assertion::JsonEquals(path, value) => serde_json::from_slice::<Value>(body)
+                .is_ok_and(|document| path.find(&document) == Some(value)),
+            Assertion::HeaderPresent(name) => headers.contains_key(name),
+            Assertion::MaxBodySize(max) => body_size <= *max,
+        };
+        match holds {
+            true => Ok(()),
+            false => Err(AssertionError::new(self)),
+        }
+    }
+
+    /// Checks all assertions, failing with the first that doesn't hold.
+    pub fn check_all(
+        assertions: &[Assertion],
+        headers: &HeaderMap,
+        body: &[u8],
+        body_size: u64,
+    ) -> Result<(), AssertionError> {
+        assertions
+            .iter()
+            .try_for_each(|a| a.check(headers, body, body_size))
+    }
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Assertion::BodyContains(text) => write!(f, "body contains `{text}`"),
+            Assertion::BodyMatches(regex) => write!(f, "body matches `{regex}`"),
+            Assertion::JsonEquals(path, value) => write!(f, "`{path}` equals `{value}`"),
+            Assertion::HeaderPresent(name) => write!(f, "header `{name}` is present"),
+            Assertion::MaxBodySize(max) => write!(f, "body has at most {max} bytes"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE, ETAG};
+    use regex::bytes::Regex;
+    use serde_json::json;
+
+    use super::Assertion;
+
+    #[test]
+    fn test_check() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let body = br#"{"status": "ok", "items": [{"id": 7}]}"#;
+        let check = |a: Assertion| a.check(&headers, body, body.len() as u64).is_ok();
+
+        assert!(check(Assertion::BodyContains("\"ok\"".to_string())));
+        assert!(!check(Assertion::BodyContains("error".to_string())));
+        assert!(check(Assertion::BodyMatches(
+            Regex::new(r#""id": \d+"#).unwrap()
+        )));
+        let path = "$.items[0].id".parse().unwrap();
+        assert!(check(Assertion::JsonEquals(path, json!(7))));
+        let path = "$.status".parse().unwrap();
+        assert!(!check(Assertion::JsonEquals(path, json!("error"))));
+        assert!(check(Assertion::HeaderPresent(CONTENT_TYPE)));
+        assert!(!check(Assertion::HeaderPresent(ETAG)));
+        assert!(check(Assertion::MaxBodySize(100)));
+        assert!(!check(Assertion::MaxBodySize(10)));
+    }
+
+    #[test]
+    fn test_json_assertion_fails_for_other_bodies() {
+        let path = "$.status".parse().unwrap();
+        let assertion = Assertion::JsonEquals(path, json!("ok"));
+        let error = assertion
+            .check(&HeaderMap::new(), b"<html>", 6)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Assertion failed: `$.status` equals `\"ok\"`"
+        );
+    }
+}
@@ -1,66 +1,467 @@
-use std::time::Duration;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
 
 use anyhow::Context;
-use hyper::{Client, Uri};
-use tokio::{sync::mpsc, time::Instant};
+use hdrhistogram::Histogram;
+use hyper::header::{HeaderMap, HeaderValue, COOKIE};
+use rand::rngs::StdRng;
+use tokio::{
+    sync::{mpsc, watch, Mutex, OwnedSemaphorePermit, Semaphore},
+    time::{interval_at, sleep_until, timeout_at, Instant},
+};
 
-use crate::{RequestReport, StatusOnlyHttpClient};
+use crate::{
+    cookie::CookieJar,
+    feeder::{FeedOrder, Feeder},
+    generator,
+    histogram::{self, RequestHistograms, DEFAULT_PRECISION},
+    mix::RequestMix,
+    profile::{LoadProfile, ProfileState},
+    scenario::{Scenario, Step},
+    template::{RequestTemplate, Variables},
+    BenchmarkClient, Engine, ErrorKind, HttpVersion, Outcome, Phase, Phases, RequestReport,
+    RequestSender, RequestSpec, Timeouts, TlsOptions,
+};
 
 #[derive(Debug)]
 pub struct BenchmarkUpdate {
     pub connection_id: u64,
+    pub stream_id: u64,
     pub current_request: u64,
 }
 
 pub struct BenchmarkParameters {
     pub connections: u64,
     pub requests: u64,
-    pub target_uri: Uri,
+    /// The endpoints the requests are spread over.
+    pub mix: RequestMix,
+    /// Every connection runs this scenario as a virtual user instead of sending requests from
+    /// `mix`, and `requests` counts the iterations of the scenario.
+    pub scenario: Option<Scenario>,
+    /// Every connection keeps the cookies of the responses and sends them along, like a browser.
+    pub cookies: bool,
+    /// Rows of test data for the `{{column}}` placeholders of the requests.
+    pub feeder: Option<Arc<Feeder>>,
+    /// Makes the random values of the run repeat: the template functions, the picked endpoints
+    /// and the random rows of the feeder.
+    pub seed: Option<u64>,
+    pub rate: Option<RateParameters>,
+    /// Stop sending after this time instead of after `requests`. In-flight requests are drained.
+    pub duration: Option<Duration>,
+    /// Vary the number of active connections over time instead of using `connections` throughout.
+    /// The run ends with the last stage.
+    pub profile: Option<LoadProfile>,
+    /// Significant figures of the latency histograms.
+    pub precision: u8,
+    /// Stop the run early once too many requests failed with an error.
+    pub max_errors: Option<ErrorLimit>,
+    pub timeouts: Timeouts,
+    /// Only used for `https` targets.
+    pub tls: TlsOptions,
+    pub http_version: HttpVersion,
+    /// Concurrent requests on every connection: multiplexed HTTP/2 streams, or pipelined requests
+    /// with the raw engine. With hyper and HTTP/1.1 every stream needs a connection of its own.
+    pub streams: u64,
+    pub engine: Engine,
+    /// Open a new connection after this many requests, instead of keeping it alive.
+    pub requests_per_connection: Option<u64>,
+}
+
+/// Open model: requests are scheduled at a fixed rate, independent of the response times.
+/// The connections act as a bounded pool of in-flight requests.
+#[derive(Debug, Clone)]
+pub struct RateParameters {
+    pub requests_per_sec: f64,
+    pub overflow: OverflowPolicy,
+}
+
+/// What to do with a scheduled request when every connection is busy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for the next free connection and send the request late.
+    Delay,
+    /// Skip the request.
+    Drop,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delay" => Ok(OverflowPolicy::Delay),
+            "drop" => Ok(OverflowPolicy::Drop),
+            _ => Err(format!("`{s}` isn't one of `delay` or `drop`")),
+        }
+    }
+}
+
+/// How many requests may fail with an error before a run is aborted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorLimit {
+    Count(u64),
+    /// Only checked once `MIN_REQUESTS_FOR_ERROR_RATE` requests are done.
+    Percent(f64),
+}
+
+const MIN_REQUESTS_FOR_ERROR_RATE: u64 = 100;
+
+impl FromStr for ErrorLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("`{s}` isn't a number of errors like `100` or `5%`");
+        match s.strip_suffix('%') {
+            Some(percent) => match percent.parse::<f64>() {
+                Ok(p) if (0.0..=100.0).contains(&p) => Ok(ErrorLimit::Percent(p)),
+                _ => Err(invalid()),
+            },
+            None => s.parse().map(ErrorLimit::Count).map_err(|_| invalid()),
+        }
+    }
+}
+
+/// Error accounting shared by all connections of a run.
+#[derive(Debug)]
+pub struct ErrorBudget {
+    limit: ErrorLimit,
+    requests: AtomicU64,
+    errors: AtomicU64,
+    exhausted: AtomicBool,
+}
+
+impl ErrorBudget {
+    pub fn new(limit: ErrorLimit) -> Self {
+        ErrorBudget {
+            limit,
+            requests: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            exhausted: AtomicBool::new(false),
+        }
+    }
+
+    /// Counts a finished request. Returns `true` once the run should stop.
+    pub fn record(&self, is_error: bool) -> bool {
+        let requests = self.requests.fetch_add(1, Ordering::Relaxed) + 1;
+        let errors = self.errors.fetch_add(is_error as u64, Ordering::Relaxed) + is_error as u64;
+        let exceeded = match self.limit {
+            ErrorLimit::Count(max) => errors > max,
+            ErrorLimit::Percent(max) => {
+                requests >= MIN_REQUESTS_FOR_ERROR_RATE
+                    && errors as f64 * 100.0 / requests as f64 > max
+            }
+        };
+        if exceeded {
+            self.exhausted.store(true, Ordering::Relaxed);
+        }
+        self.is_exhausted()
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::Relaxed)
+    }
 }
 
 pub struct ConnectionParameters {
     pub connection_id: u64,
-    pub target_uri: Uri,
+    pub stream_id: u64,
+    pub mix: RequestMix,
+    /// Run instead of requests from `mix`, `num_requests` then counts its iterations.
+    pub scenario: Option<Scenario>,
+    /// Keep a cookie jar.
+    pub cookies: bool,
+    pub feeder: Option<Arc<Feeder>>,
+    /// Of the random values of the connection, which are unpredictable without one.
+    pub seed: Option<u64>,
     pub num_requests: u64,
+    pub deadline: Option<Instant>,
+    pub profile: Option<watch::Receiver<ProfileState>>,
+    pub precision: u8,
+    pub error_budget: Option<Arc<ErrorBudget>>,
 }
 
 impl ConnectionParameters {
-    pub fn new(connection_id: u64, target_uri: Uri, num_requests: u64) -> Self {
+    pub fn new(connection_id: u64, mix: RequestMix, num_requests: u64) -> Self {
         ConnectionParameters {
             connection_id,
-            target_uri,
+            stream_id: 0,
+            mix,
+            scenario: None,
+            cookies: false,
+            feeder: None,
+            seed: None,
             num_requests,
+            deadline: None,
+            profile: None,
+            precision: DEFAULT_PRECISION,
+            error_budget: None,
         }
     }
 }
 
 pub struct BenchmarkReport {
     pub reports: Vec<ConnectionReport>,
+    pub sent_requests: u64,
     pub ok_requests: u64,
     pub failed_requests: u64,
+    pub error_requests: u64,
+    pub timeout_requests: u64,
+    pub goaway_requests: u64,
+    pub reset_requests: u64,
+    pub pipeline_closed_requests: u64,
+    pub assertion_failed_requests: u64,
+    /// TCP connect times of the requests of all outcomes that opened a new connection.
+    pub connect_times: Histogram<u64>,
+    /// Bytes of all request bodies.
+    pub sent_bytes: u64,
+    /// Bytes of all response bodies.
+    pub received_bytes: u64,
+    /// Number of errors by kind.
+    pub errors: HashMap<ErrorKind, u64>,
+    /// Whether the run was stopped early because of too many errors.
+    pub aborted: bool,
     pub max_duration_ms: u64,
     pub total_duration_ms: u64,
+    pub delayed_requests: u64,
+    pub dropped_requests: u64,
+    /// Latencies of all connections, merged by outcome.
+    pub histograms: HashMap<Outcome, RequestHistograms>,
+    /// The same as `histograms`, for every stage of the load profile.
+    pub stages: Vec<HashMap<Outcome, RequestHistograms>>,
+    /// The same as `histograms`, for every endpoint of a request mix with more than one, or every
+    /// step of the scenario.
+    pub endpoints: Vec<HashMap<Outcome, RequestHistograms>>,
+    /// Latencies of the iterations of the scenario, by the outcome of their last step.
+    pub transactions: HashMap<Outcome, RequestHistograms>,
+    pub failed_transactions: u64,
+    /// Times a cookie jar got its first cookies.
+    pub established_sessions: u64,
+    /// Times a cookie jar lost its last cookies, because they expired or were deleted.
+    pub lost_sessions: u64,
+}
+
+impl BenchmarkReport {
+    fn new(reports: Vec<ConnectionReport>, total_duration_ms: u64) -> Self {
+        let (ok_requests, failed_requests, max_duration_ms) = calc_stats(&reports);
+        let error_requests = reports.iter().map(|r| r.error_requests).sum();
+        let timeout_requests = reports.iter().map(|r| r.timeout_requests).sum();
+        let goaway_requests = reports.iter().map(|r| r.goaway_requests).sum();
+        let reset_requests = reports.iter().map(|r| r.reset_requests).sum();
+        let pipeline_closed_requests = reports.iter().map(|r| r.pipeline_closed_requests).sum();
+        let assertion_failed_requests = reports.iter().map(|r| r.assertion_failed_requests).sum();
+        let histograms = histogram::merge(reports.iter().map(|r| &r.requests));
+        let errors = histograms
+            .iter()
+            .filter_map(|(outcome, h)| match outcome {
+                Outcome::Error(kind) => Some((*kind, h.duration.len())),
+                Outcome::Status(_) | Outcome::AssertionFailed => None,
+            })
+            .collect();
+        let precision = reports.first().map_or(DEFAULT_PRECISION, |r| r.precision);
+        let mut connect_times = histogram::latency_histogram(precision);
+        for h in histograms.values().filter_map(|h| h.phase(Phase::Connect)) {
+            connect_times
+                .add(h)
+                .expect("Histograms with different bounds");
+        }
+        let sent_bytes = histograms.values().map(|h| h.sent_bytes).sum();
+        let received_bytes = histograms.values().map(|h| h.received_bytes).sum();
+        let sent_requests = reports.iter().map(|r| r.num_requests).sum();
+        let num_stages = reports.iter().map(|r| r.stages.len()).max().unwrap_or(0);
+        let stages = (0..num_stages)
+            .map(|stage| histogram::merge(reports.iter().filter_map(|r| r.stages.get(stage))))
+            .collect();
+        let transactions = histogram::merge(reports.iter().map(|r| &r.transactions));
+        let failed_transactions = reports.iter().map(|r| r.failed_transactions).sum();
+        let established_sessions = reports.iter().map(|r| r.established_sessions).sum();
+        let lost_sessions = reports.iter().map(|r| r.lost_sessions).sum();
+        let num_endpoints = reports.iter().map(|r| r.endpoints.len()).max().unwrap_or(0);
+        let endpoints = (0..num_endpoints)
+            .map(|i| histogram::merge(reports.iter().filter_map(|r| r.endpoints.get(i))))
+            .collect();
+
+        BenchmarkReport {
+            reports,
+            sent_requests,
+            ok_requests,
+            failed_requests,
+            error_requests,
+            timeout_requests,
+            goaway_requests,
+            reset_requests,
+            pipeline_closed_requests,
+            assertion_failed_requests,
+            connect_times,
+            sent_bytes,
+            received_bytes,
+            errors,
+            aborted: false,
+            max_duration_ms,
+            total_duration_ms,
+            delayed_requests: 0,
+            dropped_requests: 0,
+            histograms,
+            stages,
+            endpoints,
+            transactions,
+            failed_transactions,
+            established_sessions,
+            lost_sessions,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ConnectionReport {
     pub connection_id: u64,
+    /// The stream of the connection the requests were sent on, until the streams are merged.
+    pub stream_id: u64,
+    /// Concurrent requests on the connection.
+    pub streams: u64,
     pub num_requests: u64,
     pub ok_requests: u64,
     pub failed_requests: u64,
+    /// Requests that didn't get a response because of an error.
+    pub error_requests: u64,
+    /// Requests that didn't get a response in time.
+    pub timeout_requests: u64,
+    /// Requests that failed because the server shut down the HTTP/2 connection (GOAWAY).
+    pub goaway_requests: u64,
+    /// Requests that failed because the server reset their HTTP/2 stream (RST_STREAM).
+    pub reset_requests: u64,
+    /// Requests that were lost because the connection closed while they were pipelined behind
+    /// another request.
+    pub pipeline_closed_requests: u64,
+    /// Responses with an expected status that failed an assertion of the request.
+    pub assertion_failed_requests: u64,
     pub duration: Duration,
-    pub requests: Vec<RequestReport>,
+    /// Latencies of the requests by outcome.
+    pub requests: HashMap<Outcome, RequestHistograms>,
+    /// Latencies of the requests by stage of the load profile and outcome.
+    pub stages: Vec<HashMap<Outcome, RequestHistograms>>,
+    /// The stage new requests are recorded in.
+    pub current_stage: Option<usize>,
+    /// Latencies of the requests by endpoint of the request mix and outcome.
+    pub endpoints: Vec<HashMap<Outcome, RequestHistograms>>,
+    /// The endpoint (or step of the scenario) new requests are recorded in, if the mix has more
+    /// than one.
+    pub current_endpoint: Option<usize>,
+    /// Latencies of the iterations of the scenario by the outcome of their last step. They are
+    /// the sums over the steps, without the think times.
+    pub transactions: HashMap<Outcome, RequestHistograms>,
+    /// Iterations that stopped at a step that failed.
+    pub failed_transactions: u64,
+    /// Times the cookie jar got its first cookies.
+    pub established_sessions: u64,
+    /// Times the cookie jar lost its last cookies.
+    pub lost_sessions: u64,
+    pub precision: u8,
 }
 
 impl ConnectionReport {
     pub fn new(connection_id: u64, num_requests: u64) -> Self {
+        ConnectionReport::with_precision(connection_id, num_requests, DEFAULT_PRECISION)
+    }
+
+    pub fn with_precision(connection_id: u64, num_requests: u64, precision: u8) -> Self {
         ConnectionReport {
             connection_id,
+            stream_id: 0,
+            streams: 1,
             num_requests,
             ok_requests: 0,
             failed_requests: 0,
+            error_requests: 0,
+            timeout_requests: 0,
+            goaway_requests: 0,
+            reset_requests: 0,
+            pipeline_closed_requests: 0,
+            assertion_failed_requests: 0,
             duration: Duration::default(),
-            requests: Vec::with_capacity(num_requests as usize),
+            requests: HashMap::new(),
+            stages: Vec::new(),
+            current_stage: None,
+            endpoints: Vec::new(),
+            current_endpoint: None,
+            transactions: HashMap::new(),
+            failed_transactions: 0,
+            established_sessions: 0,
+            lost_sessions: 0,
+            precision,
+        }
+    }
+
+    /// Requests that didn't get a response, for whatever reason.
+    pub fn unanswered_requests(&self) -> u64 {
+        self.error_requests + self.timeout_requests
+    }
+
+    /// Adds the requests of another stream of the same connection.
+    pub fn merge(&mut self, other: ConnectionReport) {
+        self.streams += other.streams;
+        self.num_requests += other.num_requests;
+        self.ok_requests += other.ok_requests;
+        self.failed_requests += other.failed_requests;
+        self.error_requests += other.error_requests;
+        self.timeout_requests += other.timeout_requests;
+        self.goaway_requests += other.goaway_requests;
+        self.reset_requests += other.reset_requests;
+        self.pipeline_closed_requests += other.pipeline_closed_requests;
+        self.assertion_failed_requests += other.assertion_failed_requests;
+        self.duration = self.duration.max(other.duration);
+        self.requests = histogram::merge([&self.requests, &other.requests]);
+        let num_stages = self.stages.len().max(other.stages.len());
+        self.stages = (0..num_stages)
+            .map(|stage| {
+                let stages = self.stages.get(stage).into_iter();
+                histogram::merge(stages.chain(other.stages.get(stage)))
+            })
+            .collect();
+        self.transactions = histogram::merge([&self.transactions, &other.transactions]);
+        self.failed_transactions += other.failed_transactions;
+        self.established_sessions += other.established_sessions;
+        self.lost_sessions += other.lost_sessions;
+        let num_endpoints = self.endpoints.len().max(other.endpoints.len());
+        self.endpoints = (0..num_endpoints)
+            .map(|i| {
+                let endpoints = self.endpoints.get(i).into_iter();
+                histogram::merge(endpoints.chain(other.endpoints.get(i)))
+            })
+            .collect();
+    }
+
+    pub fn record(&mut self, report: &RequestReport) {
+        self.requests
+            .entry(report.outcome)
+            .or_insert_with(|| RequestHistograms::new(self.precision))
+            .record(report);
+
+        if let Some(stage) = self.current_stage {
+            if self.stages.len() <= stage {
+                self.stages.resize_with(stage + 1, HashMap::new);
+            }
+            self.stages[stage]
+                .entry(report.outcome)
+                .or_insert_with(|| RequestHistograms::new(self.precision))
+                .record(report);
+        }
+
+        if let Some(endpoint) = self.current_endpoint {
+            if self.endpoints.len() <= endpoint {
+                self.endpoints.resize_with(endpoint + 1, HashMap::new);
+            }
+            self.endpoints[endpoint]
+                .entry(report.outcome)
+                .or_insert_with(|| RequestHistograms::new(self.precision))
+                .record(report);
         }
     }
 }
@@ -69,55 +470,229 @@ pub async fn run(
     params: &BenchmarkParameters,
     tx_update: mpsc::UnboundedSender<BenchmarkUpdate>,
 ) -> anyhow::Result<BenchmarkReport> {
-    let BenchmarkParameters {
-        connections,
-        requests,
-        target_uri,
-    } = params;
+    if params.scenario.is_none() {
+        let feeder = params.feeder.as_deref();
+        Feeder::check_columns(feeder, params.mix.variables()).map_err(anyhow::Error::msg)?;
+    }
+    params.mix.restart_sequences();
+    if let Some(scenario) = &params.scenario {
+        scenario.restart_sequences();
+    }
+    if let Some(rate) = &params.rate {
+        return run_rate(params, rate, tx_update).await;
+    }
+
+    let BenchmarkParameters { requests, mix, .. } = params;
+    let connections = &match &params.profile {
+        Some(profile) => profile.max_connections(),
+        None => params.connections,
+    };
+    let streams = params.streams.max(1);
+    let lanes = connections * streams;
 
     let mut clients = Vec::with_capacity(*connections as usize);
-    let mut handles = Vec::with_capacity(*connections as usize);
-    let mut reports = Vec::with_capacity(*connections as usize);
+    let mut handles = Vec::with_capacity(lanes as usize);
+    let mut reports: Vec<ConnectionReport> = Vec::with_capacity(*connections as usize);
 
+    let tls = params.tls.config_for(mix.tls_uri())?;
     for _ in 0..*connections {
-        clients.push(Client::builder().build_http());
+        clients.push(BenchmarkClient::new(
+            params.engine,
+            params.timeouts,
+            tls.as_ref(),
+            params.http_version,
+            params.requests_per_connection,
+        )?);
     }
 
-    let number_of_connection_with_one_more_requests =
-        (params.requests % params.connections) as usize;
+    let number_of_lanes_with_one_more_requests = requests % lanes;
 
     let start_instant = Instant::now();
+    let mut deadline = params.duration.map(|d| start_instant + d);
+
+    let error_budget = params.max_errors.map(|l| Arc::new(ErrorBudget::new(l)));
+
+    let rx_profile = params.profile.clone().map(|profile| {
+        deadline = Some(start_instant + profile.total_duration());
+        let (tx_profile, rx_profile) = watch::channel(profile.initial_state());
+        tokio::spawn(async move { profile.drive(start_instant, tx_profile).await });
+        rx_profile
+    });
 
     for (id, c) in clients.into_iter().enumerate() {
-        let mut param =
-            ConnectionParameters::new(id as u64, target_uri.clone(), requests / connections);
-        if id < number_of_connection_with_one_more_requests {
-            param.num_requests += 1;
+        for stream_id in 0..streams {
+            let mut param = ConnectionParameters::new(id as u64, mix.clone(), requests / lanes);
+            param.scenario = params.scenario.clone();
+            param.cookies = params.cookies;
+            param.feeder = params.feeder.clone();
+            param.stream_id = stream_id;
+            param.precision = params.precision;
+            let lane = id as u64 * streams + stream_id;
+            param.seed = params.seed.map(|seed| seed.wrapping_add(lane));
+            if lane < number_of_lanes_with_one_more_requests {
+                param.num_requests += 1;
+            }
+            if deadline.is_some() {
+                param.num_requests = u64::MAX;
+                param.deadline = deadline;
+            }
+            param.profile = rx_profile.clone();
+            param.error_budget = error_budget.clone();
+            let h = tokio::spawn(connection_task(c.clone(), param, tx_update.clone()));
+            handles.push(h);
         }
-        let h = tokio::spawn(connection_task(c, param, tx_update.clone()));
-        handles.push(h);
     }
 
     for h in handles {
         let await_result = h.await;
         let connection_result = await_result.context("Failed to await for task")?;
         let report = connection_result.context("A connection failed")?;
-        reports.push(report);
+        merge_streams(&mut reports, report);
     }
 
     let total_duration_ms = start_instant.elapsed().as_millis() as u64;
 
-    let (ok_requests, failed_requests, max_duration_ms) = calc_stats(&reports);
+    Ok(BenchmarkReport {
+        aborted: error_budget.is_some_and(|b| b.is_exhausted()),
+        ..BenchmarkReport::new(reports, total_duration_ms)
+    })
+}
+
+async fn run_rate(
+    params: &BenchmarkParameters,
+    rate: &RateParameters,
+    tx_update: mpsc::UnboundedSender<BenchmarkUpdate>,
+) -> anyhow::Result<BenchmarkReport> {
+    let BenchmarkParameters {
+        connections,
+        requests,
+        mix,
+        precision,
+        ..
+    } = params;
+
+    anyhow::ensure!(
+        params.scenario.is_none(),
+        "Scenarios can't be scheduled at a fixed rate"
+    );
+    anyhow::ensure!(
+        !params.cookies,
+        "Cookies are only kept without a fixed rate"
+    );
+    let feeder = params.feeder.as_deref();
+    anyhow::ensure!(
+        feeder.is_none_or(|f| f.order() != FeedOrder::Unique),
+        "Rows can't be unique per connection at a fixed rate"
+    );
+    let tls = params.tls.config_for(mix.tls_uri())?;
+    let streams = params.streams.max(1);
+    let error_budget = params.max_errors.map(|l| Arc::new(ErrorBudget::new(l)));
+    let pool = Arc::new(Semaphore::new((connections * streams) as usize));
+    let (tx_schedule, rx_schedule) = mpsc::unbounded_channel();
+    let rx_schedule = Arc::new(Mutex::new(rx_schedule));
+
+    let start_instant = Instant::now();
+
+    let clients = (0..*connections)
+        .map(|_| {
+            let tls = tls.as_ref();
+            BenchmarkClient::new(
+                params.engine,
+                params.timeouts,
+                tls,
+                params.http_version,
+                params.requests_per_connection,
+            )
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let handles = (0..*connections)
+        .zip(clients)
+        .flat_map(|(id, client)| (0..streams).map(move |stream_id| (id, stream_id, client.clone())))
+        .map(|(id, stream_id, client)| {
+            let mut report = ConnectionReport::with_precision(id, 0, *precision);
+            report.stream_id = stream_id;
+            let seed = params
+                .seed
+                .map(|seed| seed.wrapping_add(id * streams + stream_id));
+            let user = VirtualUser::new(params.feeder.clone(), false, seed);
+            tokio::spawn(pooled_connection_task(
+                client,
+                report,
+                mix.clone(),
+                user,
+                rx_schedule.clone(),
+                error_budget.clone(),
+                tx_update.clone(),
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    let mut delayed_requests = 0;
+    let mut dropped_requests = 0;
+    // `interval_at` panics on a zero period, which rates above 1e9/s round down to
+    let period = Duration::from_secs_f64(1.0 / rate.requests_per_sec).max(Duration::from_nanos(1));
+    let mut interval = interval_at(start_instant, period);
+    let (requests, deadline) = match params.duration {
+        Some(duration) => (u64::MAX, Some(start_instant + duration)),
+        None => (*requests, None),
+    };
+
+    for _ in 0..requests {
+        let intended_start = interval.tick().await;
+        if deadline.is_some_and(|d| intended_start >= d)
+            || error_budget.as_ref().is_some_and(|b| b.is_exhausted())
+            || feeder.is_some_and(Feeder::is_exhausted)
+        {
+            break;
+        }
+        let permit = match pool.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) if rate.overflow == OverflowPolicy::Drop => {
+                dropped_requests += 1;
+                continue;
+            }
+            Err(_) => {
+                delayed_requests += 1;
+                pool.clone()
+                    .acquire_owned()
+                    .await
+                    .context("The connection pool was closed")?
+            }
+        };
+        tx_schedule
+            .send((intended_start, permit))
+            .context("All connections terminated before the schedule was done")?;
+    }
+    drop(tx_schedule);
+
+    let mut reports = Vec::with_capacity(*connections as usize);
+    for h in handles {
+        let await_result = h.await;
+        let connection_result = await_result.context("Failed to await for task")?;
+        let report = connection_result.context("A connection failed")?;
+        merge_streams(&mut reports, report);
+    }
+
+    let total_duration_ms = start_instant.elapsed().as_millis() as u64;
 
     Ok(BenchmarkReport {
-        reports,
-        ok_requests,
-        failed_requests,
-        max_duration_ms,
-        total_duration_ms,
+        delayed_requests,
+        dropped_requests,
+        aborted: error_budget.is_some_and(|b| b.is_exhausted()),
+        ..BenchmarkReport::new(reports, total_duration_ms)
     })
 }
 
+/// Adds the report of a stream to the report of its connection. The streams of a connection have to
+/// come one after the other.
+fn merge_streams(reports: &mut Vec<ConnectionReport>, report: ConnectionReport) {
+    match reports.last_mut() {
+        Some(last) if last.connection_id == report.connection_id => last.merge(report),
+        _ => reports.push(report),
+    }
+}
+
 pub fn calc_stats(results: &[ConnectionReport]) -> (u64, u64, u64) {
     let ok_requests = results.iter().map(|r| r.ok_requests).sum();
     let failed_requests = results.iter().map(|r| r.failed_requests).sum();
@@ -132,52 +707,427 @@ pub fn calc_stats(results: &[ConnectionReport]) -> (u64, u64, u64) {
 }
 
 pub async fn connection_task(
-    client: impl StatusOnlyHttpClient,
-    params: ConnectionParameters,
+    client: impl RequestSender,
+    mut params: ConnectionParameters,
     tx_update: mpsc::UnboundedSender<BenchmarkUpdate>,
 ) -> anyhow::Result<ConnectionReport> {
-    let mut conn_report = ConnectionReport::new(params.connection_id, params.num_requests);
+    let mut conn_report = ConnectionReport::with_precision(
+        params.connection_id,
+        params.num_requests,
+        params.precision,
+    );
+    conn_report.stream_id = params.stream_id;
 
     let start_instant = Instant::now();
 
-    for n in 0..params.num_requests {
-        do_request(&client, &params.target_uri, &mut conn_report, n, &tx_update).await?;
+    let mut n = 0;
+    let mut sent = 0;
+    let mut user = VirtualUser::new(params.feeder.clone(), params.cookies, params.seed);
+    while n < params.num_requests && params.deadline.is_none_or(|d| Instant::now() < d) {
+        if let Some(rx_profile) = &mut params.profile {
+            let active = wait_until_active(rx_profile, params.connection_id, params.deadline);
+            match active.await {
+                Some(stage) => conn_report.current_stage = Some(stage),
+                None => break,
+            }
+        }
+        if !user.feed(n == 0) {
+            break;
+        }
+        let errors_before = conn_report.unanswered_requests();
+        match &params.scenario {
+            Some(scenario) => {
+                let deadline = params.deadline;
+                let iteration = &mut conn_report;
+                let user = &mut user;
+                sent += do_transaction(&client, scenario, deadline, iteration, user, n, &tx_update)
+                    .await?;
+            }
+            None => {
+                let (endpoint, request) = params.mix.pick(&mut user.rng);
+                conn_report.current_endpoint = params.mix.is_mixed().then_some(endpoint);
+                if let Some(request) = render(request, &mut user, &mut conn_report) {
+                    let user = &mut user;
+                    do_user_request(&client, &request, user, &mut conn_report, n, &tx_update)
+                        .await?;
+                    sent += 1;
+                }
+            }
+        }
+        n += 1;
+        if let Some(budget) = &params.error_budget {
+            if budget.record(conn_report.unanswered_requests() > errors_before) {
+                break;
+            }
+        }
     }
 
+    conn_report.num_requests = sent;
     conn_report.duration = start_instant.elapsed();
 
     Ok(conn_report)
 }
 
-pub async fn do_request(
-    client: &impl StatusOnlyHttpClient,
-    target_uri: &Uri,
+/// Runs one iteration of the scenario: its steps in order with their think times in between, until
+/// a step fails or the deadline passes. Records the steps and, unless the deadline cut it short,
+/// the whole transaction. The values the steps extract are kept in the variables of the user for
+/// the later steps and iterations. Returns the number of requests sent.
+async fn do_transaction(
+    client: &impl RequestSender,
+    scenario: &Scenario,
+    deadline: Option<Instant>,
+    conn_report: &mut ConnectionReport,
+    user: &mut VirtualUser,
+    current_iteration: u64,
+    tx_update: &mpsc::UnboundedSender<BenchmarkUpdate>,
+) -> anyhow::Result<u64> {
+    let mut transaction = RequestReport {
+        outcome: Outcome::Status(0),
+        duration: Duration::ZERO,
+        corrected_duration: Duration::ZERO,
+        phases: Phases::default(),
+        sent_bytes: 0,
+        received_bytes: 0,
+        headers: None,
+        body: None,
+    };
+    let mut sent = 0;
+    for (i, step) in scenario.steps.iter().enumerate() {
+        conn_report.current_endpoint = Some(i);
+        let Some(request) = render(&step.request, user, conn_report) else {
+            transaction.outcome = Outcome::Error(ErrorKind::Other);
+            conn_report.failed_transactions += 1;
+            break;
+        };
+        let report = do_user_request(
+            client,
+            &request,
+            user,
+            conn_report,
+            current_iteration,
+            tx_update,
+        );
+        let report = report.await?;
+        sent += 1;
+        transaction.outcome = report.outcome;
+        transaction.duration += report.duration;
+        transaction.corrected_duration += report.corrected_duration;
+        transaction.sent_bytes += report.sent_bytes;
+        transaction.received_bytes += report.received_bytes;
+        let ok =
+            matches!(report.outcome, Outcome::Status(s) if request.expected_status.contains(s));
+        if !ok {
+            conn_report.failed_transactions += 1;
+            break;
+        }
+        if !extract(step, &report, &mut user.variables) {
+            // the response passed, but the later steps can't go on without the value
+            transaction.outcome = Outcome::AssertionFailed;
+            conn_report.failed_transactions += 1;
+            break;
+        }
+        if i + 1 < scenario.steps.len() {
+            let think_until = Instant::now() + step.think_time;
+            if deadline.is_some_and(|d| think_until >= d) {
+                return Ok(sent);
+            }
+            sleep_until(think_until).await;
+        }
+    }
+    conn_report
+        .transactions
+        .entry(transaction.outcome)
+        .or_insert_with(|| RequestHistograms::new(conn_report.precision))
+        .record(&transaction);
+    Ok(sent)
+}
+
+/// Fills in the variables of the user and the values of the functions. A request that can't be
+/// built isn't sent, and is recorded as an error.
+fn render<'a>(
+    request: &'a RequestTemplate,
+    user: &mut VirtualUser,
+    conn_report: &mut ConnectionReport,
+) -> Option<Cow<'a, RequestSpec>> {
+    match request.render(&user.variables, &mut user.rng) {
+        Ok(request) => Some(request),
+        Err(e) => {
+            log::warn!(
+                "A request can't be built (connection #{}): {e}",
+                conn_report.connection_id
+            );
+            conn_report.error_requests += 1;
+            conn_report.record(&RequestReport {
+                outcome: Outcome::Error(ErrorKind::Other),
+                duration: Duration::ZERO,
+                corrected_duration: Duration::ZERO,
+                phases: Phases::default(),
+                sent_bytes: 0,
+                received_bytes: 0,
+                headers: None,
+                body: None,
+            });
+            None
+        }
+    }
+}
+
+/// What a connection keeps between its requests.
+#[derive(Debug)]
+struct VirtualUser {
+    /// The values extracted by the steps of the scenario and the row of the feeder.
+    variables: Variables,
+    /// Only if cookies are kept.
+    cookies: Option<CookieJar>,
+    feeder: Option<Arc<Feeder>>,
+    /// For the template functions, the picked endpoints and the random rows.
+    rng: StdRng,
+}
+
+impl VirtualUser {
+    fn new(feeder: Option<Arc<Feeder>>, cookies: bool, seed: Option<u64>) -> Self {
+        VirtualUser {
+            variables: Variables::new(),
+            cookies: cookies.then(CookieJar::new),
+            feeder,
+            rng: generator::rng(seed),
+        }
+    }
+
+    /// Takes the row of the next request or iteration, see `Feeder::feed`. Returns `false` if the
+    /// feeder ran out of rows.
+    fn feed(&mut self, first: bool) -> bool {
+        match &self.feeder {
+            Some(feeder) => feeder.feed(&mut self.variables, first, &mut self.rng),
+            None => true,
+        }
+    }
+}
+
+/// Sends the request with the cookies of the user, and stores the cookies of the response. Counts
+/// the sessions that were established or lost on the way.
+async fn do_user_request(
+    client: &impl RequestSender,
+    request: &RequestSpec,
+    user: &mut VirtualUser,
     conn_report: &mut ConnectionReport,
     current_request: u64,
     tx_update: &mpsc::UnboundedSender<BenchmarkUpdate>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<RequestReport> {
+    let Some(jar) = &mut user.cookies else {
+        return do_request(client, request, conn_report, current_request, tx_update).await;
+    };
+    let now = SystemTime::now();
+    let had_session = !jar.is_empty();
+    jar.remove_expired(now);
+    if had_session && jar.is_empty() {
+        conn_report.lost_sessions += 1;
+    }
+    let mut request = request.clone();
+    if let Some(cookies) = jar.header(&request.uri, now) {
+        // a `Cookie` header of the request itself is kept, the jar adds to it
+        let cookies = match request.headers.get(COOKIE) {
+            Some(own) => {
+                let mut joined = own.as_bytes().to_vec();
+                joined.extend_from_slice(b"; ");
+                joined.extend_from_slice(cookies.as_bytes());
+                HeaderValue::from_bytes(&joined).unwrap_or(cookies)
+            }
+            None => cookies,
+        };
+        request.headers.insert(COOKIE, cookies);
+    }
+    request.keep_headers = true;
+
+    let report = do_request(client, &request, conn_report, current_request, tx_update).await?;
+    if let Some(headers) = &report.headers {
+        let had_session = !jar.is_empty();
+        jar.store(&request.uri, headers, SystemTime::now());
+        match (had_session, jar.is_empty()) {
+            (false, false) => conn_report.established_sessions += 1,
+            (true, true) => conn_report.lost_sessions += 1,
+            _ => {}
+        }
+    }
+    Ok(report)
+}
+
+/// Stores the values the extractors of the step find in the response. Returns `false` if one of them
+/// is missing.
+fn extract(step: &Step, report: &RequestReport, variables: &mut Variables) -> bool {
+    let empty = HeaderMap::new();
+    let headers = report.headers.as_ref().unwrap_or(&empty);
+    let body = report.body.as_deref().unwrap_or_default();
+    for extractor in &step.extractors {
+        let Some(value) = extractor.extract(headers, body) else {
+            log::debug!("`{}` didn't find `{}`", step.name, extractor.variable);
+            return false;
+        };
+        variables.insert(extractor.variable.clone(), value);
+    }
+    true
+}
+
+/// Waits until the load profile needs this connection. Returns the current stage, or `None` if the
+/// run is over before that.
+async fn wait_until_active(
+    rx_profile: &mut watch::Receiver<ProfileState>,
+    connection_id: u64,
+    deadline: Option<Instant>,
+) -> Option<usize> {
+    let active = rx_profile.wait_for(|s| s.connections > connection_id);
+    let state = match deadline {
+        Some(deadline) => timeout_at(deadline, active).await.ok()?,
+        None => active.await,
+    };
+    state.ok().map(|s| s.stage)
+}
+
+async fn pooled_connection_task(
+    client: impl RequestSender,
+    mut conn_report: ConnectionReport,
+    mix: RequestMix,
+    mut user: VirtualUser,
+    rx_schedule: Arc<Mutex<mpsc::UnboundedReceiver<(Instant, OwnedSemaphorePermit)>>>,
+    error_budget: Option<Arc<ErrorBudget>>,
+    tx_update: mpsc::UnboundedSender<BenchmarkUpdate>,
+) -> anyhow::Result<ConnectionReport> {
     let start_instant = Instant::now();
 
-    let status_code = client.get(target_uri.clone()).await.context(format!(
-        "A request failed (connection #{})",
-        conn_report.connection_id
-    ))?;
-    if status_code < 408 {
-        conn_report.ok_requests += 1;
-    } else {
-        conn_report.failed_requests += 1;
+    let mut n = 0;
+    loop {
+        let next = rx_schedule.lock().await.recv().await;
+        let Some((intended_start, permit)) = next else {
+            break;
+        };
+        if !user.feed(n == 0) {
+            // the scheduler stops, the already scheduled requests are skipped
+            continue;
+        }
+        let errors_before = conn_report.unanswered_requests();
+        let (endpoint, request) = mix.pick(&mut user.rng);
+        conn_report.current_endpoint = mix.is_mixed().then_some(endpoint);
+        let Some(request) = render(request, &mut user, &mut conn_report) else {
+            continue;
+        };
+        conn_report.num_requests += 1;
+        do_scheduled_request(
+            &client,
+            &request,
+            intended_start,
+            &mut conn_report,
+            n,
+            &tx_update,
+        )
+        .await?;
+        drop(permit);
+        n += 1;
+        if let Some(budget) = &error_budget {
+            // the scheduler stops, the already scheduled requests are drained
+            budget.record(conn_report.unanswered_requests() > errors_before);
+        }
     }
 
-    let duration = Instant::now().duration_since(start_instant);
+    conn_report.duration = start_instant.elapsed();
+
+    Ok(conn_report)
+}
+
+pub async fn do_request(
+    client: &impl RequestSender,
+    request: &RequestSpec,
+    conn_report: &mut ConnectionReport,
+    current_request: u64,
+    tx_update: &mpsc::UnboundedSender<BenchmarkUpdate>,
+) -> anyhow::Result<RequestReport> {
+    let intended_start = Instant::now();
+    do_scheduled_request(
+        client,
+        request,
+        intended_start,
+        conn_report,
+        current_request,
+        tx_update,
+    )
+    .await
+}
 
-    conn_report.requests.push(RequestReport {
-        status_code,
+/// Sends a request that was meant to go out at `intended_start`. The gap between the intended and
+/// the actual send time is part of the corrected duration (see coordinated omission). Returns the
+/// report the request was recorded with.
+pub async fn do_scheduled_request(
+    client: &impl RequestSender,
+    request: &RequestSpec,
+    intended_start: Instant,
+    conn_report: &mut ConnectionReport,
+    current_request: u64,
+    tx_update: &mpsc::UnboundedSender<BenchmarkUpdate>,
+) -> anyhow::Result<RequestReport> {
+    let start_instant = Instant::now();
+    let (result, stats) = client.send_with_stats(request).await;
+    // opening a connection or waiting for a turn on a pipelined one isn't part of the latency
+    let start_instant = stats.written.unwrap_or(start_instant);
+
+    let outcome = match (result, &stats.failed_assertion) {
+        (Ok(_), Some(e)) => {
+            log::debug!("{e} (connection #{})", conn_report.connection_id);
+            conn_report.assertion_failed_requests += 1;
+            Outcome::AssertionFailed
+        }
+        (Ok(status_code), None) if request.expected_status.contains(status_code) => {
+            conn_report.ok_requests += 1;
+            Outcome::Status(status_code)
+        }
+        (Ok(status_code), None) => {
+            conn_report.failed_requests += 1;
+            Outcome::Status(status_code)
+        }
+        (Err(e), _) => {
+            log::debug!(
+                "A request failed (connection #{}): {e:#}",
+                conn_report.connection_id
+            );
+            let kind = ErrorKind::classify(&e);
+            match kind {
+                ErrorKind::Timeout => conn_report.timeout_requests += 1,
+                ErrorKind::GoAway => {
+                    conn_report.goaway_requests += 1;
+                    conn_report.error_requests += 1;
+                }
+                ErrorKind::StreamReset => {
+                    conn_report.reset_requests += 1;
+                    conn_report.error_requests += 1;
+                }
+                ErrorKind::PipelineClosed => {
+                    conn_report.pipeline_closed_requests += 1;
+                    conn_report.error_requests += 1;
+                }
+                _ => conn_report.error_requests += 1,
+            }
+            Outcome::Error(kind)
+        }
+    };
+
+    let end_instant = Instant::now();
+    let duration = end_instant.duration_since(start_instant);
+    let corrected_duration = end_instant.duration_since(intended_start);
+
+    let report = RequestReport {
+        outcome,
         duration,
-    });
+        corrected_duration,
+        phases: stats.phases,
+        sent_bytes: request.body.len() as u64,
+        received_bytes: stats.received_bytes,
+        headers: stats.headers,
+        body: stats.body,
+    };
+    conn_report.record(&report);
 
-    if (current_request % 100) == 0 {
+    if current_request.is_multiple_of(100) {
         let s = BenchmarkUpdate {
             connection_id: conn_report.connection_id,
+            stream_id: conn_report.stream_id,
             current_request,
         };
         tx_update
@@ -185,19 +1135,46 @@ pub async fn do_request(
             .context("The result channel was closed before the connection was done!")?;
     }
 
-    Ok(())
+    Ok(report)
 }
 
 #[cfg(test)]
 mod tests {
     use anyhow::{anyhow, Result};
     use async_trait::async_trait;
-    use hyper::Uri;
-    use tokio::sync::mpsc;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use hyper::{
+        header::{COOKIE, SET_COOKIE},
+        server::conn::Http,
+        service::service_fn,
+        Body, Method, Request, Response, Uri,
+    };
+    use tokio::{
+        net::TcpListener,
+        sync::{mpsc, watch, Mutex, Semaphore},
+        time::Instant,
+    };
 
     use crate::{
-        cli::{connection_task, ConnectionParameters},
-        StatusOnlyHttpClient,
+        assertion::Assertion,
+        cli::{
+            connection_task, do_scheduled_request, pooled_connection_task, run,
+            BenchmarkParameters, ConnectionParameters, ConnectionReport, ErrorBudget, ErrorLimit,
+            OverflowPolicy, RateParameters, VirtualUser,
+        },
+        feeder::{FeedEnd, FeedOrder, Feeder},
+        mix::{Endpoint, RequestMix},
+        profile::ProfileState,
+        scenario::{Scenario, Step},
+        Engine, ErrorKind, HttpVersion, HyperClient, Outcome, RequestSender, RequestSpec, Timeouts,
+        TlsOptions,
     };
 
     struct MockHttpClient {
@@ -213,8 +1190,8 @@ mod tests {
     }
 
     #[async_trait]
-    impl StatusOnlyHttpClient for MockHttpClient {
-        async fn get(&self, _uri: Uri) -> Result<u16> {
+    impl RequestSender for MockHttpClient {
+        async fn send(&self, _request: &RequestSpec) -> Result<u16> {
             self.fixed_status_response.ok_or(anyhow!("error"))
         }
     }
@@ -239,19 +1216,667 @@ mod tests {
         assert_eq!(res.failed_requests, 10);
     }
 
+    #[tokio::test]
+    async fn test_with_custom_expected_status() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let client = MockHttpClient::with_result(Some(404));
+        let mut params = common_settings();
+        let mut request = RequestSpec::get(Uri::from_static("http://dummy"));
+        request.expected_status = "2xx,404".parse().unwrap();
+        params.mix = request.into();
+        let res = connection_task(client, params, tx).await;
+        let res = res.expect("do not expect a result");
+        assert_eq!(res.ok_requests, 10);
+        assert_eq!(res.failed_requests, 0);
+    }
+
     #[tokio::test]
     async fn test_with_error() {
         let (tx, _rx) = mpsc::unbounded_channel();
         let client = MockHttpClient::with_result(None);
         let res = connection_task(client, common_settings(), tx).await;
-        res.expect_err("expect a error");
+        let res = res.expect("do not expect a result");
+        assert_eq!(res.ok_requests, 0);
+        assert_eq!(res.error_requests, 10);
+        assert_eq!(
+            res.requests[&Outcome::Error(ErrorKind::Other)]
+                .duration
+                .len(),
+            10
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stops_after_max_errors() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let client = MockHttpClient::with_result(None);
+        let mut params = common_settings();
+        let budget = Arc::new(ErrorBudget::new(ErrorLimit::Count(3)));
+        params.error_budget = Some(budget.clone());
+        let res = connection_task(client, params, tx).await;
+        let res = res.expect("do not expect a result");
+        assert_eq!(res.error_requests, 4);
+        assert!(budget.is_exhausted());
+    }
+
+    #[tokio::test]
+    async fn test_timeouts_are_counted_separately() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let timeouts = Timeouts {
+            request: Some(Duration::from_millis(10)),
+            ..Timeouts::default()
+        };
+        let client = HyperClient::new(timeouts, None, HttpVersion::Http1, None);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}/", listener.local_addr().unwrap());
+        let mut params = common_settings();
+        params.mix = RequestSpec::get(uri.parse().unwrap()).into();
+        params.num_requests = 2;
+        let res = connection_task(client, params, tx).await;
+        let res = res.expect("do not expect a result");
+        assert_eq!(res.timeout_requests, 2);
+        assert_eq!(res.error_requests, 0);
+        assert_eq!(res.unanswered_requests(), 2);
+    }
+
+    #[test]
+    fn test_error_limit_from_str() {
+        assert_eq!("5".parse(), Ok(ErrorLimit::Count(5)));
+        assert_eq!("2.5%".parse(), Ok(ErrorLimit::Percent(2.5)));
+        assert!("101%".parse::<ErrorLimit>().is_err());
+        assert!("many".parse::<ErrorLimit>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pooled_connection_runs_scheduled_requests() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let pool = Arc::new(Semaphore::new(3));
+        let (tx_schedule, rx_schedule) = mpsc::unbounded_channel();
+        for _ in 0..3 {
+            let permit = pool.clone().acquire_owned().await.unwrap();
+            tx_schedule.send((Instant::now(), permit)).unwrap();
+        }
+        drop(tx_schedule);
+
+        let client = MockHttpClient::with_result(Some(200));
+        let mix = common_settings().mix;
+        let rx_schedule = Arc::new(Mutex::new(rx_schedule));
+        let report = ConnectionReport::new(0, 0);
+        let user = VirtualUser::new(None, false, None);
+        let res = pooled_connection_task(client, report, mix, user, rx_schedule, None, tx);
+        let res = res.await;
+        let res = res.expect("do not expect a result");
+        assert_eq!(res.num_requests, 3);
+        assert_eq!(res.ok_requests, 3);
+        assert_eq!(pool.available_permits(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_corrected_duration_includes_send_delay() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let client = MockHttpClient::with_result(Some(200));
+        let mut report = ConnectionReport::new(0, 1);
+        let intended_start = Instant::now() - Duration::from_millis(50);
+        let request = RequestSpec::get(Uri::from_static("http://dummy"));
+        do_scheduled_request(&client, &request, intended_start, &mut report, 0, &tx)
+            .await
+            .expect("do not expect an error");
+        let res = &report.requests[&Outcome::Status(200)];
+        assert_eq!(res.duration.len(), 1);
+        // the single sample may be rounded down to its bucket in `min()`
+        assert!(res.corrected_duration.max() >= res.duration.max() + 50_000);
+    }
+
+    #[tokio::test]
+    async fn test_stops_at_deadline() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let client = MockHttpClient::with_result(Some(200));
+        let mut params = common_settings();
+        params.num_requests = u64::MAX;
+        params.deadline = Some(Instant::now());
+        let res = connection_task(client, params, tx).await;
+        let res = res.expect("do not expect a result");
+        assert_eq!(res.num_requests, 0);
+    }
+
+    #[tokio::test]
+    async fn test_records_by_stage() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let client = MockHttpClient::with_result(Some(200));
+        let (_tx_profile, rx_profile) = watch::channel(ProfileState {
+            stage: 1,
+            connections: 1,
+        });
+        let mut params = common_settings();
+        params.profile = Some(rx_profile);
+        let res = connection_task(client, params, tx).await;
+        let res = res.expect("do not expect a result");
+        assert_eq!(res.stages.len(), 2);
+        assert!(res.stages[0].is_empty());
+        assert_eq!(res.stages[1][&Outcome::Status(200)].duration.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_inactive_connection_stops_at_deadline() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let client = MockHttpClient::with_result(Some(200));
+        let (_tx_profile, rx_profile) = watch::channel(ProfileState {
+            stage: 0,
+            connections: 0,
+        });
+        let mut params = common_settings();
+        params.profile = Some(rx_profile);
+        params.deadline = Some(Instant::now() + Duration::from_millis(10));
+        let res = connection_task(client, params, tx).await;
+        let res = res.expect("do not expect a result");
+        assert_eq!(res.num_requests, 0);
+    }
+
+    /// Serves HTTP/1.1, or HTTP/2 without TLS (h2c). Responds with `hello`, or fails the request
+    /// if `reset` (for HTTP/2 by resetting the stream).
+    async fn server(version: HttpVersion, reset: bool) -> Uri {
+        counting_server(version, reset).await.0
+    }
+
+    /// Like `server`, and counts the connections it accepted.
+    async fn counting_server(version: HttpVersion, reset: bool) -> (Uri, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}/", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::Relaxed);
+                let service = service_fn(move |_| async move {
+                    match reset {
+                        true => Err(anyhow!("reset")),
+                        false => Ok(Response::new(Body::from("hello"))),
+                    }
+                });
+                let mut http = Http::new();
+                http.http2_only(version == HttpVersion::Http2);
+                tokio::spawn(http.serve_connection(stream, service));
+            }
+        });
+        (uri.parse().unwrap(), connections)
+    }
+
+    fn http2_settings(uri: Uri) -> BenchmarkParameters {
+        BenchmarkParameters {
+            connections: 2,
+            requests: 81,
+            mix: RequestSpec::get(uri).into(),
+            scenario: None,
+            cookies: false,
+            feeder: None,
+            seed: None,
+            rate: None,
+            duration: None,
+            profile: None,
+            precision: 3,
+            max_errors: None,
+            timeouts: Timeouts::default(),
+            tls: TlsOptions::default(),
+            http_version: HttpVersion::Http2,
+            streams: 4,
+            engine: Engine::Hyper,
+            requests_per_connection: None,
+        }
+    }
+
+    fn http1_settings(uri: Uri) -> BenchmarkParameters {
+        BenchmarkParameters {
+            http_version: HttpVersion::Http1,
+            streams: 1,
+            ..http2_settings(uri)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http2_streams_share_a_connection() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (uri, connections) = counting_server(HttpVersion::Http2, false).await;
+        let mut params = http2_settings(uri);
+        params.connections = 1;
+        let res = run(&params, tx).await.expect("do not expect an error");
+        assert_eq!(res.ok_requests, 81);
+        assert_eq!(res.reports.len(), 1);
+        assert_eq!(res.reports[0].streams, 4);
+        assert_eq!(res.reports[0].num_requests, 81);
+        assert_eq!(connections.load(Ordering::Relaxed), 1);
+    }
+
+    /// Serves HTTP/1.1, responding after `delay`.
+    async fn slow_server(delay: Duration) -> Uri {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(move |_| async move {
+                    tokio::time::sleep(delay).await;
+                    Ok::<_, anyhow::Error>(Response::new(Body::empty()))
+                });
+                tokio::spawn(Http::new().serve_connection(stream, service));
+            }
+        });
+        uri.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rate_delays_or_drops_requests_while_busy() {
+        let uri = slow_server(Duration::from_millis(100)).await;
+        let mut params = http1_settings(uri);
+        params.connections = 1;
+        params.requests = 5;
+        for (overflow, delayed, dropped) in
+            [(OverflowPolicy::Delay, 4, 0), (OverflowPolicy::Drop, 0, 4)]
+        {
+            params.rate = Some(RateParameters {
+                requests_per_sec: 100.0,
+                overflow,
+            });
+            let (tx, _rx) = mpsc::unbounded_channel();
+            let res = run(&params, tx).await.expect("do not expect an error");
+            assert_eq!(res.delayed_requests, delayed, "{overflow:?}");
+            assert_eq!(res.dropped_requests, dropped, "{overflow:?}");
+            assert_eq!(res.ok_requests, 5 - dropped);
+        }
+
+        params.rate = Some(RateParameters {
+            requests_per_sec: 1e12,
+            overflow: OverflowPolicy::Drop,
+        });
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let res = run(&params, tx).await.expect("do not expect an error");
+        assert_eq!(res.ok_requests + res.dropped_requests, 5);
+    }
+
+    #[tokio::test]
+    async fn test_http2_stream_resets_are_counted() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let params = http2_settings(server(HttpVersion::Http2, true).await);
+        let res = run(&params, tx).await.expect("do not expect an error");
+        assert_eq!(res.reset_requests, 81);
+        assert_eq!(res.error_requests, 81);
+        assert_eq!(res.errors[&ErrorKind::StreamReset], 81);
+    }
+
+    #[tokio::test]
+    async fn test_raw_engine_pipelines_requests() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut params = http2_settings(server(HttpVersion::Http1, false).await);
+        params.http_version = HttpVersion::Http1;
+        params.engine = Engine::Raw;
+        let res = run(&params, tx).await.expect("do not expect an error");
+        assert_eq!(res.ok_requests, 81);
+        assert_eq!(res.reports.len(), 2);
+        assert_eq!(res.histograms[&Outcome::Status(200)].duration.len(), 81);
+    }
+
+    #[tokio::test]
+    async fn test_counts_body_bytes() {
+        let uri = server(HttpVersion::Http1, false).await;
+        for engine in [Engine::Hyper, Engine::Raw] {
+            let (tx, _rx) = mpsc::unbounded_channel();
+            let mut params = http1_settings(uri.clone());
+            params.engine = engine;
+            let mut request = RequestSpec::new(Method::POST, uri.clone());
+            request.body = "name=joe".into();
+            params.mix = request.into();
+            let res = run(&params, tx).await.expect("do not expect an error");
+            assert_eq!(res.ok_requests, 81);
+            assert_eq!(res.sent_bytes, 81 * 8);
+            assert_eq!(res.received_bytes, 81 * 5);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_new_connection_every_n_requests() {
+        let uri = server(HttpVersion::Http1, false).await;
+        for engine in [Engine::Hyper, Engine::Raw] {
+            let (tx, _rx) = mpsc::unbounded_channel();
+            let mut params = http1_settings(uri.clone());
+            params.connections = 1;
+            params.requests = 10;
+            params.engine = engine;
+            params.requests_per_connection = Some(3);
+            let res = run(&params, tx).await.expect("do not expect an error");
+            assert_eq!(res.ok_requests, 10);
+            assert_eq!(res.connect_times.len(), 4);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_assertions_are_their_own_outcome() {
+        let uri = server(HttpVersion::Http1, false).await;
+        for engine in [Engine::Hyper, Engine::Raw] {
+            let (tx, _rx) = mpsc::unbounded_channel();
+            let mut params = http1_settings(uri.clone());
+            params.engine = engine;
+            let mut request = RequestSpec::get(uri.clone());
+            request.assertions = vec![
+                Assertion::BodyContains("hell".to_string()),
+                Assertion::MaxBodySize(5),
+            ];
+            params.mix = request.clone().into();
+            let res = run(&params, tx).await.expect("do not expect an error");
+            assert_eq!(res.ok_requests, 81);
+
+            let (tx, _rx) = mpsc::unbounded_channel();
+            request.assertions = vec![Assertion::BodyContains("world".to_string())];
+            params.mix = request.into();
+            let res = run(&params, tx).await.expect("do not expect an error");
+            assert_eq!(res.ok_requests, 0);
+            assert_eq!(res.assertion_failed_requests, 81);
+            assert_eq!(res.histograms[&Outcome::AssertionFailed].duration.len(), 81);
+            assert!(res.errors.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_spread_over_the_endpoints() {
+        let uri = server(HttpVersion::Http1, false).await;
+        for engine in [Engine::Hyper, Engine::Raw] {
+            let (tx, _rx) = mpsc::unbounded_channel();
+            let mut params = http1_settings(uri.clone());
+            params.engine = engine;
+            let mut write = RequestSpec::new(Method::POST, uri.clone());
+            write.expected_status = "201".parse().unwrap();
+            let endpoints = [
+                ("read", 3, RequestSpec::get(uri.clone())),
+                ("write", 1, write),
+            ];
+            let endpoints = endpoints.map(|(name, weight, request)| Endpoint {
+                name: name.to_string(),
+                weight,
+                request,
+            });
+            params.mix = RequestMix::new(endpoints.to_vec()).unwrap();
+            let res = run(&params, tx).await.expect("do not expect an error");
+            let [read, write] = &res.endpoints[..] else {
+                panic!("expected two endpoints");
+            };
+            let reads = read[&Outcome::Status(200)].duration.len();
+            let writes = write[&Outcome::Status(200)].duration.len();
+            assert_eq!(reads + writes, 81);
+            assert!(reads > writes);
+            assert_eq!(res.ok_requests, reads);
+            assert_eq!(res.failed_requests, writes);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_virtual_users_run_the_scenario() {
+        let uri = server(HttpVersion::Http1, false).await;
+        let step = |name: &str, expect: &str| Step {
+            name: name.to_string(),
+            request: {
+                let mut request = RequestSpec::get(uri.clone());
+                request.expected_status = expect.parse().unwrap();
+                request.into()
+            },
+            think_time: Duration::from_millis(10),
+            extractors: Vec::new(),
+        };
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut params = http1_settings(uri.clone());
+        params.requests = 4;
+        let steps = vec![
+            step("login", "2xx"),
+            step("cart", "2xx"),
+            step("pay", "2xx"),
+        ];
+        params.scenario = Some(Scenario { steps });
+        let res = run(&params, tx).await.expect("do not expect an error");
+        assert_eq!(res.sent_requests, 12);
+        assert_eq!(res.endpoints.len(), 3);
+        assert_eq!(res.transactions[&Outcome::Status(200)].duration.len(), 4);
+        assert_eq!(res.failed_transactions, 0);
+
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let steps = vec![
+            step("login", "2xx"),
+            step("cart", "404"),
+            step("pay", "2xx"),
+        ];
+        params.scenario = Some(Scenario { steps });
+        let res = run(&params, tx).await.expect("do not expect an error");
+        assert_eq!(res.sent_requests, 8);
+        assert_eq!(res.failed_requests, 4);
+        assert_eq!(res.endpoints.len(), 2);
+        assert_eq!(res.failed_transactions, 4);
+    }
+
+    #[tokio::test]
+    async fn test_steps_use_the_values_of_earlier_steps() {
+        // responds with the path of the request
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri: Uri = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|request: Request<Body>| async move {
+                    let path = request.uri().path().to_string();
+                    Ok::<_, anyhow::Error>(Response::new(Body::from(path)))
+                });
+                tokio::spawn(Http::new().serve_connection(stream, service));
+            }
+        });
+        let content = r#"[
+            {"url": "/items/42", "extract": {"id": {"regex": "/items/(\\d+)"}}},
+            {"url": "/orders/{{id}}", "extract": {"order": {"regex": "^/orders/42$"}}},
+            {"url": "/", "extract": {"missing": {"regex": "nothing"}}}
+        ]"#;
+        let base = RequestSpec::get(uri.clone());
+        let scenario = Scenario::parse(content, &base, &[]).unwrap();
+        for engine in [Engine::Hyper, Engine::Raw] {
+            let (tx, _rx) = mpsc::unbounded_channel();
+            let mut params = http1_settings(uri.clone());
+            params.requests = 4;
+            params.engine = engine;
+            let mut two_steps = scenario.clone();
+            two_steps.steps.truncate(2);
+            params.scenario = Some(two_steps);
+            let res = run(&params, tx).await.expect("do not expect an error");
+            assert_eq!(res.transactions[&Outcome::Status(200)].duration.len(), 4);
+            assert_eq!(res.failed_transactions, 0);
+
+            let (tx, _rx) = mpsc::unbounded_channel();
+            params.scenario = Some(scenario.clone());
+            let res = run(&params, tx).await.expect("do not expect an error");
+            assert_eq!(res.sent_requests, 12);
+            assert_eq!(res.ok_requests, 12);
+            assert_eq!(
+                res.transactions[&Outcome::AssertionFailed].duration.len(),
+                4
+            );
+            assert_eq!(res.failed_transactions, 4);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_steps_that_cant_be_built_are_errors() {
+        let uri = server(HttpVersion::Http1, false).await;
+        // the extracted `hello` makes a URL with a space
+        let content = r#"[
+            {"url": "/", "extract": {"greeting": {"regex": "(hello)"}}},
+            {"url": "/{{greeting}} there"}
+        ]"#;
+        let scenario = Scenario::parse(content, &RequestSpec::get(uri.clone()), &[]).unwrap();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut params = http1_settings(uri);
+        params.requests = 4;
+        params.scenario = Some(scenario);
+        let res = run(&params, tx).await.expect("do not expect an error");
+        assert_eq!(res.sent_requests, 4);
+        assert_eq!(res.error_requests, 4);
+        assert_eq!(res.errors[&ErrorKind::Other], 4);
+        assert_eq!(
+            res.endpoints[1][&Outcome::Error(ErrorKind::Other)]
+                .duration
+                .len(),
+            4
+        );
+        let failed = &res.transactions[&Outcome::Error(ErrorKind::Other)];
+        assert_eq!(failed.duration.len(), 4);
+        assert_eq!(res.failed_transactions, 4);
+    }
+
+    #[tokio::test]
+    async fn test_virtual_users_keep_their_cookies() {
+        // `/login` starts a session, `/logout` ends it, `/cart` needs it
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri: Uri = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|request: Request<Body>| async move {
+                    let logged_in = request.headers().get(COOKIE).is_some_and(|c| c == "id=1");
+                    let response = match (request.uri().path(), logged_in) {
+                        ("/login", _) => Response::builder().header(SET_COOKIE, "id=1; Path=/"),
+                        ("/logout", _) => Response::builder().header(SET_COOKIE, "id=; Max-Age=0"),
+                        ("/cart", true) => Response::builder(),
+                        _ => Response::builder().status(401),
+                    };
+                    response.body(Body::empty())
+                });
+                tokio::spawn(Http::new().serve_connection(stream, service));
+            }
+        });
+        let content = r#"[{"url": "/login"}, {"url": "/cart"}, {"url": "/logout"}]"#;
+        let scenario = Scenario::parse(content, &RequestSpec::get(uri.clone()), &[]).unwrap();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut params = http1_settings(uri);
+        params.requests = 4;
+        params.scenario = Some(scenario);
+        params.cookies = true;
+        let res = run(&params, tx).await.expect("do not expect an error");
+        assert_eq!(res.ok_requests, 12);
+        assert_eq!(res.failed_transactions, 0);
+        assert_eq!(res.established_sessions, 4);
+        assert_eq!(res.lost_sessions, 4);
+
+        let (tx, _rx) = mpsc::unbounded_channel();
+        params.cookies = false;
+        let res = run(&params, tx).await.expect("do not expect an error");
+        assert_eq!(res.failed_transactions, 4);
+        assert_eq!(res.established_sessions, 0);
+    }
+
+    /// Serves HTTP/1.1, and records the paths of the requests.
+    async fn recording_server() -> (Uri, Arc<std::sync::Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}/", listener.local_addr().unwrap());
+        let paths = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = paths.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let seen = seen.clone();
+                let service = service_fn(move |request: Request<Body>| {
+                    seen.lock().unwrap().push(request.uri().path().to_string());
+                    async { Ok::<_, anyhow::Error>(Response::new(Body::empty())) }
+                });
+                tokio::spawn(Http::new().serve_connection(stream, service));
+            }
+        });
+        (uri.parse().unwrap(), paths)
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_filled_from_the_feeder() {
+        let (uri, paths) = recording_server().await;
+        let uri = format!("{uri}items/{{{{id}}}}");
+        let rows = Feeder::parse_csv("id,name\n1,a\n2,b\n3,c\n").unwrap();
+        let mut params = http1_settings(uri.parse().unwrap());
+        params.requests = 10;
+        let (tx, _rx) = mpsc::unbounded_channel();
+        assert!(run(&params, tx).await.is_err());
+
+        let feeder = Feeder::new(rows.clone(), FeedOrder::Sequential, FeedEnd::Stop);
+        params.feeder = Some(Arc::new(feeder.unwrap()));
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let res = run(&params, tx).await.expect("do not expect an error");
+        assert_eq!(res.sent_requests, 3);
+        let mut sent = std::mem::take(&mut *paths.lock().unwrap());
+        sent.sort();
+        assert_eq!(sent, ["/items/1", "/items/2", "/items/3"]);
+
+        let feeder = Feeder::new(rows, FeedOrder::Unique, FeedEnd::Recycle);
+        params.feeder = Some(Arc::new(feeder.unwrap()));
+        params.requests = 4;
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let res = run(&params, tx).await.expect("do not expect an error");
+        assert_eq!(res.sent_requests, 4);
+        let mut sent = std::mem::take(&mut *paths.lock().unwrap());
+        sent.sort();
+        assert_eq!(sent, ["/items/1", "/items/1", "/items/2", "/items/2"]);
+    }
+
+    #[tokio::test]
+    async fn test_seeded_runs_repeat() {
+        let (uri, paths) = recording_server().await;
+        let uri = format!("{uri}items/{{{{random_string(8)}}}}");
+        let mut params = http1_settings(uri.parse().unwrap());
+        params.requests = 6;
+        let mut runs = Vec::new();
+        for seed in [7, 7, 8] {
+            params.seed = Some(seed);
+            let (tx, _rx) = mpsc::unbounded_channel();
+            run(&params, tx).await.expect("do not expect an error");
+            let mut sent = std::mem::take(&mut *paths.lock().unwrap());
+            sent.sort();
+            runs.push(sent);
+        }
+        assert_eq!(runs[0].len(), 6);
+        assert_eq!(runs[0], runs[1]);
+        assert_ne!(runs[0], runs[2]);
+    }
+
+    #[tokio::test]
+    async fn test_seeded_runs_repeat_in_order() {
+        let (uri, paths) = recording_server().await;
+        let uri = format!("{uri}items/{{{{random_string(8)}}}}/{{{{sequence()}}}}");
+        let mut params = http1_settings(uri.parse().unwrap());
+        // a single virtual user, so that the order doesn't depend on timing
+        params.connections = 1;
+        params.requests = 6;
+        let mut runs = Vec::new();
+        for seed in [7, 7, 8] {
+            params.seed = Some(seed);
+            let (tx, _rx) = mpsc::unbounded_channel();
+            run(&params, tx).await.expect("do not expect an error");
+            runs.push(std::mem::take(&mut *paths.lock().unwrap()));
+        }
+        assert_eq!(runs[0].len(), 6);
+        assert!(runs[0][0].ends_with("/1") && runs[0][5].ends_with("/6"));
+        assert_eq!(runs[0], runs[1]);
+        assert_ne!(runs[0], runs[2]);
+    }
+
+    #[tokio::test]
+    async fn test_raw_engine_rejects_http2() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut params = http2_settings(Uri::from_static("http://dummy"));
+        params.engine = Engine::Raw;
+        assert!(run(&params, tx).await.is_err());
     }
 
     fn common_settings() -> ConnectionParameters {
         ConnectionParameters {
             connection_id: 0,
-            target_uri: Uri::from_static("http://dummy"),
+            stream_id: 0,
+            mix: RequestSpec::get(Uri::from_static("http://dummy")).into(),
+            scenario: None,
+            cookies: false,
+            feeder: None,
+            seed: None,
             num_requests: 10,
+            deadline: None,
+            profile: None,
+            precision: 3,
+            error_budget: None,
         }
     }
 }
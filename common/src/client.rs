@@ -0,0 +1,410 @@
+use std::{
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{self, Poll},
+    time::Duration,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::{
+    body::HttpBody,
+    client::{
+        connect::{dns::GaiResolver, Connect},
+        HttpConnector,
+    },
+    header::{HeaderMap, HeaderValue, CONNECTION},
+    http::uri::Scheme,
+    service::Service,
+    Body, Client, Response,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use tokio::time::{timeout, Instant};
+
+use crate::{
+    assertion::Assertion,
+    error::{AssertionError, BodyReadError},
+    raw::RawClient,
+    tls::TlsConfig,
+    HttpClient, Phases, RequestSender, RequestSpec, RequestStats,
+};
+
+/// Limits for the phases of a request. `None` waits forever.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeouts {
+    /// Establishing the TCP connection.
+    pub connect: Option<Duration>,
+    /// The whole request, from sending it until the end of the response body.
+    pub request: Option<Duration>,
+    /// The longest pause between two chunks of the response body.
+    pub idle_body: Option<Duration>,
+}
+
+/// The HTTP version spoken on every connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HttpVersion {
+    #[default]
+    Http1,
+    /// With prior knowledge for `http` targets (h2c), negotiated by ALPN for `https` targets.
+    Http2,
+}
+
+impl FromStr for HttpVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1.1" => Ok(HttpVersion::Http1),
+            "2" => Ok(HttpVersion::Http2),
+            _ => Err(format!("`{s}` isn't one of `1.1` or `2`")),
+        }
+    }
+}
+
+/// The HTTP client implementation the requests are sent with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Engine {
+    #[default]
+    Hyper,
+    /// The minimal HTTP/1.1 client of `raw`, for higher throughput. Only supports `http` targets.
+    Raw,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hyper" => Ok(Engine::Hyper),
+            "raw" => Ok(Engine::Raw),
+            _ => Err(format!("`{s}` isn't one of `hyper` or `raw`")),
+        }
+    }
+}
+
+/// The client of one connection, for the chosen engine. Clones share the connection.
+#[derive(Clone)]
+pub enum BenchmarkClient {
+    Hyper(Box<HyperClient>),
+    Raw(RawClient),
+}
+
+impl BenchmarkClient {
+    /// Opens a new connection after `requests_per_connection` requests, if given. Fails if the
+    /// engine doesn't support the TLS or HTTP version settings.
+    pub fn new(
+        engine: Engine,
+        timeouts: Timeouts,
+        tls: Option<&TlsConfig>,
+        version: HttpVersion,
+        requests_per_connection: Option<u64>,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            requests_per_connection.is_none() || version == HttpVersion::Http1,
+            "New connections after some requests are only supported with HTTP/1.1"
+        );
+        match engine {
+            Engine::Hyper => Ok(BenchmarkClient::Hyper(Box::new(HyperClient::new(
+                timeouts,
+                tls,
+                version,
+                requests_per_connection,
+            )))),
+            Engine::Raw => {
+                anyhow::ensure!(tls.is_none(), "The raw engine doesn't support https");
+                anyhow::ensure!(
+                    version == HttpVersion::Http1,
+                    "The raw engine only supports HTTP/1.1"
+                );
+                Ok(BenchmarkClient::Raw(RawClient::new(
+                    timeouts,
+                    requests_per_connection,
+                )))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl RequestSender for BenchmarkClient {
+    async fn send(&self, request: &RequestSpec) -> Result<u16> {
+        match self {
+            BenchmarkClient::Hyper(client) => client.send(request).await,
+            BenchmarkClient::Raw(client) => client.send(request).await,
+        }
+    }
+
+    async fn send_with_stats(&self, request: &RequestSpec) -> (Result<u16>, RequestStats) {
+        match self {
+            BenchmarkClient::Hyper(client) => client.send_with_stats(request).await,
+            BenchmarkClient::Raw(client) => client.send_with_stats(request).await,
+        }
+    }
+}
+
+/// The hyper client used by the benchmarks, with its timeouts applied to every request.
+#[derive(Clone)]
+pub struct HyperClient {
+    client: Connector,
+    timeouts: Timeouts,
+    requests_per_connection: Option<u64>,
+    /// Requests sent by all clones, to know when to close the connection.
+    sent_requests: Arc<AtomicU64>,
+}
+
+/// Connects over TCP, timing the DNS lookup and the connect.
+type TcpConnector = Timed<HttpConnector<Timed<GaiResolver>>>;
+
+#[derive(Clone)]
+enum Connector {
+    Http(Client<TcpConnector, Body>),
+    Https(Client<Timed<HttpsConnector<TcpConnector>>, Body>),
+}
+
+impl HyperClient {
+    /// Connects to `https` URIs over TLS if `tls` is given, and to `http` URIs in plain text.
+    /// Clones share the connection pool, so with HTTP/2 their requests are multiplexed on the
+    /// same connection. Every `requests_per_connection`th request asks to close its HTTP/1.1
+    /// connection afterwards, so that the next one opens a new one.
+    pub fn new(
+        timeouts: Timeouts,
+        tls: Option<&TlsConfig>,
+        version: HttpVersion,
+        requests_per_connection: Option<u64>,
+    ) -> Self {
+        let resolver = Timed::new(GaiResolver::new(), |p| &mut p.dns);
+        let mut connector = HttpConnector::new_with_resolver(resolver);
+        connector.set_connect_timeout(timeouts.connect);
+        let mut client_builder = Client::builder();
+        client_builder.http2_only(version == HttpVersion::Http2);
+        let client = match tls {
+            Some(tls) => {
+                connector.enforce_http(false);
+                let mut builder = HttpsConnectorBuilder::new()
+                    .with_tls_config(tls.client_config.clone())
+                    .https_or_http();
+                if let Some(server_name) = &tls.server_name {
+                    builder = builder.with_server_name(server_name.clone());
+                }
+                let connector = Timed::new(connector, |p| &mut p.connect);
+                let connector = match version {
+                    HttpVersion::Http1 => builder.enable_http1().wrap_connector(connector),
+                    HttpVersion::Http2 => builder.enable_http2().wrap_connector(connector),
+                };
+                let connector = Timed::new(connector, |p| &mut p.tls);
+                Connector::Https(client_builder.build(connector))
+            }
+            None => {
+                let connector = Timed::new(connector, |p| &mut p.connect);
+                Connector::Http(client_builder.build(connector))
+            }
+        };
+        HyperClient {
+            client,
+            timeouts,
+            requests_per_connection,
+            sent_requests: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+#[async_trait]
+impl RequestSender for HyperClient {
+    async fn send(&self, request: &RequestSpec) -> Result<u16> {
+        self.send_with_stats(request).await.0
+    }
+
+    async fn send_with_stats(&self, request: &RequestSpec) -> (Result<u16>, RequestStats) {
+        let start_instant = Instant::now();
+        let sent = self.sent_requests.fetch_add(1, Ordering::Relaxed) + 1;
+        let close = self
+            .requests_per_connection
+            .is_some_and(|n| sent.is_multiple_of(n));
+        let mut progress = ResponseProgress::default();
+        let send = async {
+            let (timeouts, progress) = (&self.timeouts, &mut progress);
+            let status = match &self.client {
+                Connector::Http(client) => {
+                    send_request(client, request, close, timeouts, progress).await
+                }
+                Connector::Https(client) => {
+                    send_request(client, request, close, timeouts, progress).await
+                }
+            };
+            (status, PHASES.with(Cell::get))
+        };
+        let (status, mut phases) = PHASES.scope(Cell::default(), send).await;
+        if request.uri.scheme() != Some(&Scheme::HTTPS) {
+            // the TLS step passes plain text connections through
+            phases.tls = None;
+        }
+        // hyper writes the request once it has a connection
+        let written = phases.connection().map(|c| start_instant + c);
+        if let Some(head) = progress.head {
+            let ttfb = head.saturating_duration_since(written.unwrap_or(start_instant));
+            phases.ttfb = Some(ttfb);
+            phases.download = status.is_ok().then(|| head.elapsed());
+        }
+        let stats = RequestStats {
+            written,
+            phases,
+            received_bytes: progress.received_bytes,
+            headers: progress.headers,
+            body: progress.body,
+            failed_assertion: progress.failed_assertion,
+        };
+        (status, stats)
+    }
+}
+
+#[async_trait]
+impl RequestSender for HttpClient {
+    async fn send(&self, request: &RequestSpec) -> Result<u16> {
+        let progress = &mut ResponseProgress::default();
+        send_request(self, request, false, &Timeouts::default(), progress).await
+    }
+}
+
+tokio::task_local! {
+    /// The phases of opening a connection for the current request, if it needed a new one.
+    static PHASES: Cell<Phases>;
+}
+
+/// Records how long a step of opening a connection takes in `PHASES`, e.g. resolving the host
+/// name. A connection that hyper opens in the background, after the request got another one,
+/// isn't recorded.
+#[derive(Clone)]
+struct Timed<S> {
+    inner: S,
+    /// The phase of the step, which doesn't include the phases recorded by the inner steps.
+    phase: fn(&mut Phases) -> &mut Option<Duration>,
+}
+
+impl<S> Timed<S> {
+    fn new(inner: S, phase: fn(&mut Phases) -> &mut Option<Duration>) -> Self {
+        Timed { inner, phase }
+    }
+}
+
+impl<S, T> Service<T> for Timed<S>
+where
+    S: Service<T>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let step = self.inner.call(target);
+        let phase = self.phase;
+        Box::pin(async move {
+            let start_instant = Instant::now();
+            let result = step.await;
+            let elapsed = start_instant.elapsed();
+            let _ = PHASES.try_with(|cell| {
+                let mut phases = cell.get();
+                let inner = phases.connection().unwrap_or_default();
+                *phase(&mut phases) = Some(elapsed.saturating_sub(inner));
+                cell.set(phases);
+            });
+            result
+        })
+    }
+}
+
+/// How far a response got, also if it failed.
+#[derive(Default)]
+struct ResponseProgress {
+    /// When the response head arrived.
+    head: Option<Instant>,
+    received_bytes: u64,
+    /// The headers, if the request asked to keep them.
+    headers: Option<HeaderMap>,
+    /// The body, if the request asked to keep it.
+    body: Option<Vec<u8>>,
+    failed_assertion: Option<AssertionError>,
+}
+
+/// Sends `Connection: close` if `close`.
+async fn send_request<C>(
+    client: &Client<C, Body>,
+    request: &RequestSpec,
+    close: bool,
+    timeouts: &Timeouts,
+    progress: &mut ResponseProgress,
+) -> Result<u16>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    let response = async {
+        let mut hyper_request = request.to_hyper_request()?;
+        if close {
+            let headers = hyper_request.headers_mut();
+            headers.insert(CONNECTION, HeaderValue::from_static("close"));
+        }
+        let response = client.request(hyper_request).await?;
+        progress.head = Some(Instant::now());
+        let status_code = response.status().into();
+        let check = !request.assertions.is_empty() && request.expected_status.contains(status_code);
+        let keep_body =
+            request.keep_body || check && request.assertions.iter().any(Assertion::needs_body);
+        let mut body = keep_body.then(Vec::new);
+        let headers = (check || request.keep_headers).then(|| response.headers().clone());
+        let received_bytes = &mut progress.received_bytes;
+        let body_size =
+            read_body(response, timeouts.idle_body, received_bytes, body.as_mut()).await?;
+        let headers = headers.unwrap_or_default();
+        let body = body.unwrap_or_default();
+        if check {
+            let checked = Assertion::check_all(&request.assertions, &headers, &body, body_size);
+            progress.failed_assertion = checked.err();
+        }
+        progress.headers = request.keep_headers.then_some(headers);
+        progress.body = request.keep_body.then_some(body);
+        Ok(status_code)
+    };
+    match timeouts.request {
+        Some(limit) => timeout(limit, response).await?,
+        None => response.await,
+    }
+}
+
+/// Reads the response body, so the connection can be reused, and returns its size. Counts its
+/// bytes in `received_bytes` and keeps them in `kept` if given.
+async fn read_body(
+    response: Response<Body>,
+    idle_timeout: Option<Duration>,
+    received_bytes: &mut u64,
+    mut kept: Option<&mut Vec<u8>>,
+) -> Result<u64> {
+    let mut body = response.into_body();
+    let mut size = 0;
+    loop {
+        let chunk = match idle_timeout {
+            Some(limit) => timeout(limit, body.data()).await?,
+            None => body.data().await,
+        };
+        match chunk {
+            Some(chunk) => {
+                let chunk = chunk.map_err(BodyReadError)?;
+                *received_bytes += chunk.len() as u64;
+                size += chunk.len() as u64;
+                if let Some(kept) = kept.as_mut() {
+                    kept.extend_from_slice(&chunk);
+                }
+            }
+            None => return Ok(size),
+        }
+    }
+}
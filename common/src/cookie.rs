@@ -0,0 +1,287 @@
+use std::{
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use hyper::{
+    header::{HeaderMap, HeaderValue, SET_COOKIE},
+    Uri,
+};
+
+/// The cookies of a virtual user. Follows the `Set-Cookie` rules of RFC 6265 for the domain,
+/// path, expiry and `Secure` attributes.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cookie {
+    name: String,
+    value: String,
+    /// Lowercase, without a leading dot.
+    domain: String,
+    /// Only sent to exactly `domain`, not to its subdomains.
+    host_only: bool,
+    path: String,
+    secure: bool,
+    /// `None` for session cookies, which live as long as the jar.
+    expires: Option<SystemTime>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        CookieJar::default()
+    }
+
+    /// Whether the jar has no cookies, expired ones included.
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+
+    /// Drops the cookies that expired by `now`.
+    pub fn remove_expired(&mut self, now: SystemTime) {
+        self.cookies.retain(|c| c.expires.is_none_or(|e| e > now));
+    }
+
+    /// The `Cookie` header for a request to `uri`, if any cookie goes there. Longer paths come
+    /// first.
+    pub fn header(&self, uri: &Uri, now: SystemTime) -> Option<HeaderValue> {
+        let host = uri.host()?.to_ascii_lowercase();
+        let path = uri.path();
+        let secure = uri.scheme_str() == Some("https");
+        let mut cookies = self
+            .cookies
+            .iter()
+            .filter(|c| match c.host_only {
+                true => c.domain == host,
+                false => domain_matches(&host, &c.domain),
+            })
+            .filter(|c| path_matches(path, &c.path))
+            .filter(|c| secure || !c.secure)
+            .filter(|c| c.expires.is_none_or(|e| e > now))
+            .collect::<Vec<_>>();
+        if cookies.is_empty() {
+            return None;
+        }
+        cookies.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+        let pairs = cookies.iter().map(|c| format!("{}={}", c.name, c.value));
+        HeaderValue::from_str(&pairs.collect::<Vec<_>>().join("; ")).ok()
+    }
+
+    /// Stores the cookies of the `Set-Cookie` headers of a response to a request to `uri`.
+    /// Cookies for other domains or that already expired are ignored, the latter remove the
+    /// cookie they replace.
+    pub fn store(&mut self, uri: &Uri, headers: &HeaderMap, now: SystemTime) {
+        for value in headers.get_all(SET_COOKIE) {
+            let cookie = value.to_str().ok().and_then(|v| Cookie::parse(v, uri, now));
+            let Some(cookie) = cookie else {
+                continue;
+            };
+            self.cookies.retain(|c| {
+                (&c.name, &c.domain, &c.path) != (&cookie.name, &cookie.domain, &cookie.path)
+            });
+            if cookie.expires.is_none_or(|e| e > now) {
+                self.cookies.push(cookie);
+            }
+        }
+    }
+}
+
+impl Cookie {
+    /// Parses a `Set-Cookie` value like `id=a3fWa; Domain=example.com; Path=/; Max-Age=3600`.
+    /// Returns `None` if it is invalid or not allowed for `uri`.
+    fn parse(set_cookie: &str, uri: &Uri, now: SystemTime) -> Option<Self> {
+        let host = uri.host()?.to_ascii_lowercase();
+        let mut attributes = set_cookie.split(';');
+        let (name, value) = attributes.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(uri.path()).to_string(),
+            secure: false,
+            expires: None,
+        };
+        let mut max_age = None;
+        for attribute in attributes {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "domain" => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    if domain.is_empty() {
+                        continue;
+                    }
+                    if !domain_matches(&host, &domain) {
+                        return None;
+                    }
+                    cookie.host_only = host == domain;
+                    cookie.domain = domain;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "max-age" => {
+                    max_age = value.parse::<i64>().ok().map(|seconds| match seconds {
+                        ..=0 => SystemTime::UNIX_EPOCH,
+                        seconds => {
+                            let max_age = Duration::from_secs(seconds as u64);
+                            now.checked_add(max_age).unwrap_or_else(far_future)
+                        }
+                    })
+                }
+                "expires" => cookie.expires = cookie.expires.or_else(|| parse_date(value)),
+                _ => {}
+            }
+        }
+        // `Max-Age` wins over `Expires`
+        cookie.expires = max_age.or(cookie.expires);
+        Some(cookie)
+    }
+}
+
+/// The end of the year 9999, for cookies whose `Max-Age` is too long for a `SystemTime`.
+fn far_future() -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(253_402_300_799)
+}
+
+/// Parses the dates of `Expires`, also with the dashes of the old Netscape format, e.g.
+/// `Wed, 21-Oct-2015 07:28:00 GMT`.
+fn parse_date(s: &str) -> Option<SystemTime> {
+    httpdate::parse_http_date(s)
+        .or_else(|_| httpdate::parse_http_date(&s.replace('-', " ")))
+        .ok()
+}
+
+/// Whether `host` is `domain` or one of its subdomains. IP addresses only match themselves.
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || host.parse::<IpAddr>().is_err()
+            && host
+                .strip_suffix(domain)
+                .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// Whether a request to `path` gets the cookies of `cookie_path`.
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/'))
+}
+
+/// The path of cookies without a `Path`: the directory of the request path.
+fn default_path(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use hyper::{
+        header::{HeaderMap, HeaderValue, SET_COOKIE},
+        Uri,
+    };
+
+    use super::CookieJar;
+
+    fn set_cookies(values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(SET_COOKIE, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    fn header(jar: &CookieJar, uri: &'static str, now: SystemTime) -> Option<String> {
+        let value = jar.header(&Uri::from_static(uri), now)?;
+        Some(value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_domain_and_path() {
+        let now = SystemTime::now();
+        let mut jar = CookieJar::new();
+        let headers = set_cookies(&[
+            "session=abc; Path=/",
+            "shared=1; Domain=.example.com; Path=/",
+            "cart=2; Path=/shop",
+            "local=3",
+            "other=4; Domain=other.com",
+        ]);
+        jar.store(
+            &Uri::from_static("http://www.example.com/app/login"),
+            &headers,
+            now,
+        );
+        assert_eq!(
+            header(&jar, "http://www.example.com/shop/items", now).unwrap(),
+            "cart=2; session=abc; shared=1"
+        );
+        assert_eq!(
+            header(&jar, "http://www.example.com/app/x", now).unwrap(),
+            "local=3; session=abc; shared=1"
+        );
+        assert_eq!(
+            header(&jar, "http://www.example.com/shopping", now).unwrap(),
+            "session=abc; shared=1"
+        );
+        assert_eq!(
+            header(&jar, "http://api.example.com/", now).unwrap(),
+            "shared=1"
+        );
+        assert_eq!(header(&jar, "http://other.com/", now), None);
+    }
+
+    #[test]
+    fn test_secure_and_expiry() {
+        let now = SystemTime::now();
+        let uri = Uri::from_static("https://example.com/");
+        let mut jar = CookieJar::new();
+        let headers = set_cookies(&[
+            "secure=1; Secure",
+            "short=2; Max-Age=60",
+            "dated=3; Expires=Wed, 21-Oct-2015 07:28:00 GMT",
+            "late=4; Expires=Wed, 21 Oct 2099 07:28:00 GMT",
+            "forever=5; Max-Age=9223372036854775807",
+        ]);
+        jar.store(&uri, &headers, now);
+        assert_eq!(
+            header(&jar, "https://example.com/", now).unwrap(),
+            "secure=1; short=2; late=4; forever=5"
+        );
+        assert_eq!(
+            header(&jar, "http://example.com/", now).unwrap(),
+            "short=2; late=4; forever=5"
+        );
+
+        let later = now + Duration::from_secs(61);
+        assert_eq!(
+            header(&jar, "http://example.com/", later).unwrap(),
+            "late=4; forever=5"
+        );
+        jar.store(&uri, &set_cookies(&["late=; Max-Age=0"]), later);
+        jar.remove_expired(later);
+        assert_eq!(
+            header(&jar, "https://example.com/", later).unwrap(),
+            "secure=1; forever=5"
+        );
+        jar.store(
+            &uri,
+            &set_cookies(&[
+                "secure=; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+                "forever=; Max-Age=0",
+            ]),
+            later,
+        );
+        assert!(jar.is_empty());
+    }
+}
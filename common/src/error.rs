@@ -0,0 +1,264 @@
+use std::{fmt, io};
+
+use crate::assertion::Assertion;
+
+/// Why a request didn't get a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ErrorKind {
+    /// The target actively refused the connection.
+    ConnectionRefused,
+    /// The connection was reset or closed before the response was complete.
+    ConnectionReset,
+    /// The host name couldn't be resolved.
+    Dns,
+    /// The TLS handshake failed, e.g. because a certificate was rejected.
+    TlsHandshake,
+    /// The TLS session failed after the handshake.
+    Tls,
+    /// The response body couldn't be read.
+    BodyRead,
+    /// The operating system or the client gave up waiting.
+    Timeout,
+    /// The response wasn't valid HTTP.
+    Protocol,
+    /// The HTTP/2 connection was shut down (GOAWAY) before the response was complete.
+    GoAway,
+    /// The HTTP/2 stream was reset (RST_STREAM) before the response was complete.
+    StreamReset,
+    /// The connection was closed while the request was pipelined behind another one.
+    PipelineClosed,
+    Other,
+}
+
+impl ErrorKind {
+    /// Finds the most specific category in the chain of causes of `error`.
+    pub fn classify(error: &anyhow::Error) -> Self {
+        error
+            .chain()
+            .find_map(|cause| {
+                if cause.is::<BodyReadError>() {
+                    return Some(ErrorKind::BodyRead);
+                }
+                if cause.is::<ProtocolError>() {
+                    return Some(ErrorKind::Protocol);
+                }
+                if cause.is::<PipelineClosedError>() {
+                    return Some(ErrorKind::PipelineClosed);
+                }
+                if let Some(e) = cause.downcast_ref::<io::Error>() {
+                    return ErrorKind::from_io(e);
+                }
+                if cause.is::<tokio::time::error::Elapsed>() {
+                    return Some(ErrorKind::Timeout);
+                }
+                if let Some(e) = cause.downcast_ref::<hyper::Error>() {
+                    return ErrorKind::from_hyper(e);
+                }
+                if let Some(e) = cause.downcast_ref::<h2::Error>() {
+                    return ErrorKind::from_h2(e);
+                }
+                // hyper's connector doesn't expose its error type, only the message
+                cause
+                    .to_string()
+                    .starts_with("dns error")
+                    .then_some(ErrorKind::Dns)
+            })
+            .unwrap_or(ErrorKind::Other)
+    }
+
+    fn from_io(e: &io::Error) -> Option<Self> {
+        // rustls reports its failures wrapped in an `io::Error`, which the connector wraps again
+        match e.get_ref() {
+            Some(inner) if inner.is::<rustls::Error>() => {
+                return inner.downcast_ref().map(ErrorKind::from_rustls)
+            }
+            Some(inner) => {
+                if let Some(kind) = inner
+                    .downcast_ref::<io::Error>()
+                    .and_then(ErrorKind::from_io)
+                {
+                    return Some(kind);
+                }
+            }
+            None => {}
+        }
+        match e.kind() {
+            io::ErrorKind::ConnectionRefused => Some(ErrorKind::ConnectionRefused),
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof => Some(ErrorKind::ConnectionReset),
+            io::ErrorKind::TimedOut => Some(ErrorKind::Timeout),
+            _ => None,
+        }
+    }
+
+    fn from_rustls(e: &rustls::Error) -> Self {
+        use rustls::{AlertDescription as Alert, Error};
+        match e {
+            Error::InvalidCertificate(_)
+            | Error::NoCertificatesPresented
+            | Error::UnsupportedNameType
+            | Error::PeerIncompatible(_)
+            | Error::InappropriateHandshakeMessage { .. }
+            | Error::HandshakeNotComplete
+            | Error::NoApplicationProtocol => ErrorKind::TlsHandshake,
+            // with TLS 1.3 a rejected client certificate is only reported after the handshake
+            Error::AlertReceived(
+                Alert::HandshakeFailure
+                | Alert::BadCertificate
+                | Alert::UnsupportedCertificate
+                | Alert::CertificateRevoked
+                | Alert::CertificateExpired
+                | Alert::CertificateUnknown
+                | Alert::CertificateRequired
+                | Alert::UnknownCA
+                | Alert::AccessDenied
+                | Alert::ProtocolVersion
+                | Alert::InsufficientSecurity,
+            ) => ErrorKind::TlsHandshake,
+            _ => ErrorKind::Tls,
+        }
+    }
+
+    fn from_hyper(e: &hyper::Error) -> Option<Self> {
+        if e.is_timeout() {
+            Some(ErrorKind::Timeout)
+        } else if e.is_parse() {
+            Some(ErrorKind::Protocol)
+        } else if e.is_incomplete_message() || e.is_closed() {
+            Some(ErrorKind::ConnectionReset)
+        } else {
+            None
+        }
+    }
+
+    fn from_h2(e: &h2::Error) -> Option<Self> {
+        if e.is_go_away() {
+            Some(ErrorKind::GoAway)
+        } else if e.is_reset() {
+            Some(ErrorKind::StreamReset)
+        } else {
+            e.get_io().and_then(ErrorKind::from_io)
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::ConnectionRefused => "connection_refused",
+            ErrorKind::ConnectionReset => "connection_reset",
+            ErrorKind::Dns => "dns",
+            ErrorKind::TlsHandshake => "tls_handshake",
+            ErrorKind::Tls => "tls",
+            ErrorKind::BodyRead => "body_read",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Protocol => "protocol",
+            ErrorKind::GoAway => "goaway",
+            ErrorKind::StreamReset => "stream_reset",
+            ErrorKind::PipelineClosed => "pipeline_closed",
+            ErrorKind::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Marks a failure while reading a response body, as opposed to getting the response headers.
+#[derive(Debug)]
+pub struct BodyReadError(pub hyper::Error);
+
+impl fmt::Display for BodyReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to read the response body: {}", self.0)
+    }
+}
+
+impl std::error::Error for BodyReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// A response that isn't valid HTTP/1.x, as found by the raw engine.
+#[derive(Debug)]
+pub struct ProtocolError(String);
+
+impl ProtocolError {
+    pub fn new(reason: &str) -> Self {
+        ProtocolError(reason.to_string())
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid HTTP response: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// A response with an expected status failed one of the assertions of the request.
+#[derive(Debug, Clone)]
+pub struct AssertionError(String);
+
+impl AssertionError {
+    pub fn new(assertion: &Assertion) -> Self {
+        AssertionError(assertion.to_string())
+    }
+}
+
+impl fmt::Display for AssertionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Assertion failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for AssertionError {}
+
+/// A pipelined request was lost because the connection ended before its response, after the
+/// response to an earlier request failed or asked to close the connection.
+#[derive(Debug)]
+pub struct PipelineClosedError;
+
+impl fmt::Display for PipelineClosedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("The connection was closed with the request still in the pipeline")
+    }
+}
+
+impl std::error::Error for PipelineClosedError {}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use anyhow::{anyhow, Context};
+    use hyper::{Client, Uri};
+
+    use super::ErrorKind;
+
+    #[test]
+    fn test_classify_io_errors() {
+        let refused = anyhow::Error::new(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert_eq!(ErrorKind::classify(&refused), ErrorKind::ConnectionRefused);
+        let reset = anyhow::Error::new(io::Error::from(io::ErrorKind::ConnectionReset))
+            .context("A request failed");
+        assert_eq!(ErrorKind::classify(&reset), ErrorKind::ConnectionReset);
+        assert_eq!(ErrorKind::classify(&anyhow!("error")), ErrorKind::Other);
+    }
+
+    #[tokio::test]
+    async fn test_classify_dns_error() {
+        let client = Client::new();
+        let error = client
+            .get(Uri::from_static("http://does-not-exist.invalid/"))
+            .await
+            .context("A request failed")
+            .unwrap_err();
+        assert_eq!(ErrorKind::classify(&error), ErrorKind::Dns);
+    }
+}
@@ -0,0 +1,80 @@
+use hyper::header::{HeaderMap, HeaderName};
+use regex::bytes::Regex;
+use serde_json::Value;
+
+use crate::json_path::JsonPath;
+
+/// Takes a value from a response and stores it in a variable of the virtual user, for the
+/// requests of later steps.
+#[derive(Debug, Clone)]
+pub struct Extractor {
+    pub variable: String,
+    pub source: Source,
+}
+
+/// Where an extractor finds its value.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// The value at the path of a JSON body, strings without their quotes.
+    Json(JsonPath),
+    /// The first capture group of the regex in the body, or the whole match without one.
+    Regex(Regex),
+    Header(HeaderName),
+}
+
+impl Extractor {
+    /// Returns `None` if the response with the `headers` and `body` doesn't have the value.
+    pub fn extract(&self, headers: &HeaderMap, body: &[u8]) -> Option<String> {
+        match &self.source {
+            Source::Json(path) => {
+                let document = serde_json::from_slice::<Value>(body).ok()?;
+                match path.find(&document)? {
+                    Value::String(s) => Some(s.clone()),
+                    value => Some(value.to_string()),
+                }
+            }
+            Source::Regex(regex) => {
+                let captures = regex.captures(body)?;
+                let value = captures.get(1).or_else(|| captures.get(0))?;
+                String::from_utf8(value.as_bytes().to_vec()).ok()
+            }
+            Source::Header(name) => {
+                let value = headers.get(name)?;
+                value.to_str().ok().map(str::to_string)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::{HeaderMap, HeaderValue, LOCATION};
+    use regex::bytes::Regex;
+
+    use super::{Extractor, Source};
+
+    #[test]
+    fn test_extract() {
+        let mut headers = HeaderMap::new();
+        headers.insert(LOCATION, HeaderValue::from_static("/items/42"));
+        let body = br#"{"token": "abc", "user": {"id": 7}, "csrf": "x-1"}"#;
+        let extract = |source| {
+            let variable = "v".to_string();
+            Extractor { variable, source }.extract(&headers, body)
+        };
+
+        let token = extract(Source::Json("$.token".parse().unwrap()));
+        assert_eq!(token.as_deref(), Some("abc"));
+        let id = extract(Source::Json("$.user.id".parse().unwrap()));
+        assert_eq!(id.as_deref(), Some("7"));
+        assert_eq!(extract(Source::Json("$.missing".parse().unwrap())), None);
+        let csrf = extract(Source::Regex(Regex::new(r#""csrf": "([^"]+)""#).unwrap()));
+        assert_eq!(csrf.as_deref(), Some("x-1"));
+        let user = extract(Source::Regex(Regex::new(r#""user""#).unwrap()));
+        assert_eq!(user.as_deref(), Some("\"user\""));
+        assert_eq!(
+            extract(Source::Header(LOCATION)).as_deref(),
+            Some("/items/42")
+        );
+    }
+}
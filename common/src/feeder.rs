@@ -0,0 +1,249 @@
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use rand::Rng;
+use serde_json::Value;
+
+use crate::template::Variables;
+
+/// The order the rows of a feeder are handed out in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedOrder {
+    /// Every request (or iteration of a scenario) takes the next row, shared by all virtual
+    /// users.
+    Sequential,
+    /// Every request takes a row at random. Never runs out.
+    Random,
+    /// Every virtual user takes the next row once and keeps it, e.g. for one account per user.
+    Unique,
+}
+
+impl FromStr for FeedOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sequential" => Ok(FeedOrder::Sequential),
+            "random" => Ok(FeedOrder::Random),
+            "unique" => Ok(FeedOrder::Unique),
+            _ => Err(format!(
+                "`{s}` isn't one of `sequential`, `random` or `unique`"
+            )),
+        }
+    }
+}
+
+/// What to do when every row was handed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedEnd {
+    /// Start over with the first row.
+    Recycle,
+    /// Stop the virtual users that need another row.
+    Stop,
+}
+
+impl FromStr for FeedEnd {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "recycle" => Ok(FeedEnd::Recycle),
+            "stop" => Ok(FeedEnd::Stop),
+            _ => Err(format!("`{s}` isn't one of `recycle` or `stop`")),
+        }
+    }
+}
+
+/// Rows of test data, shared by all connections of a run. Their columns are the variables of the
+/// `{{column}}` placeholders in the URLs, header values and bodies of the requests.
+#[derive(Debug)]
+pub struct Feeder {
+    columns: Vec<String>,
+    rows: Vec<Variables>,
+    order: FeedOrder,
+    end: FeedEnd,
+    next: AtomicUsize,
+    exhausted: AtomicBool,
+}
+
+impl Feeder {
+    pub fn new(rows: Vec<Variables>, order: FeedOrder, end: FeedEnd) -> Result<Self, String> {
+        let Some(first) = rows.first() else {
+            return Err("no rows".to_string());
+        };
+        let mut columns = first.keys().cloned().collect::<Vec<_>>();
+        columns.sort();
+        Ok(Feeder {
+            columns,
+            rows,
+            order,
+            end,
+            next: AtomicUsize::new(0),
+            exhausted: AtomicBool::new(false),
+        })
+    }
+
+    /// Parses CSV with the column names in the first line. Fields can be quoted with `"`, which
+    /// is escaped as `""`. Empty lines are skipped.
+    pub fn parse_csv(content: &str) -> Result<Vec<Variables>, String> {
+        let mut reader = csv::ReaderBuilder::new().from_reader(content.as_bytes());
+        let invalid = |e: csv::Error| match e.kind() {
+            csv::ErrorKind::UnequalLengths {
+                pos: Some(pos),
+                expected_len,
+                len,
+            } => format!(
+                "line {} has {len} fields instead of {expected_len}",
+                pos.line()
+            ),
+            _ => e.to_string(),
+        };
+        let header = reader.headers().map_err(invalid)?.clone();
+        if header.is_empty() {
+            return Err("no header line".to_string());
+        }
+        reader
+            .records()
+            .map(|record| {
+                let record = record.map_err(invalid)?;
+                let row = header.iter().zip(&record);
+                Ok(row.map(|(c, v)| (c.to_string(), v.to_string())).collect())
+            })
+            .collect()
+    }
+
+    /// Parses a JSON object per line. Strings are taken without their quotes, other values as
+    /// JSON.
+    pub fn parse_ndjson(content: &str) -> Result<Vec<Variables>, String> {
+        let lines = content.lines().filter(|l| !l.trim().is_empty());
+        lines
+            .enumerate()
+            .map(|(i, line)| {
+                let invalid = |e: String| format!("row {} isn't a JSON object: {e}", i + 1);
+                let value = serde_json::from_str::<Value>(line).map_err(|e| invalid(e.to_string()));
+                let Value::Object(object) = value? else {
+                    return Err(invalid(line.to_string()));
+                };
+                let row = object.into_iter().map(|(column, value)| match value {
+                    Value::String(s) => (column, s),
+                    value => (column, value.to_string()),
+                });
+                Ok(row.collect())
+            })
+            .collect()
+    }
+
+    /// The column names of the first row, sorted.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn order(&self) -> FeedOrder {
+        self.order
+    }
+
+    /// Puts the columns of the next row into the `variables` of a virtual user, for its next
+    /// request or iteration. With `FeedOrder::Unique` only its `first` one takes a row. Returns
+    /// `false` if the feeder ran out of rows.
+    pub fn feed(&self, variables: &mut Variables, first: bool, rng: &mut impl Rng) -> bool {
+        if self.order == FeedOrder::Unique && !first {
+            return true;
+        }
+        let Some(row) = self.next_row(rng) else {
+            return false;
+        };
+        variables.extend(row.iter().map(|(k, v)| (k.clone(), v.clone())));
+        true
+    }
+
+    /// Checks that there is a column for every variable.
+    pub fn check_columns<'a>(
+        feeder: Option<&Feeder>,
+        mut variables: impl Iterator<Item = &'a str>,
+    ) -> Result<(), String> {
+        let columns = feeder.map_or(&[][..], Feeder::columns);
+        match variables.find(|v| !columns.iter().any(|c| c == v)) {
+            Some(name) if feeder.is_none() => Err(format!("`{{{{{name}}}}}` needs a feeder")),
+            Some(name) => Err(format!("`{{{{{name}}}}}` isn't a column of the feeder")),
+            None => Ok(()),
+        }
+    }
+
+    /// Hands out the next row, or `None` once every row was handed out and they aren't recycled.
+    /// Random rows are picked with `rng`.
+    pub fn next_row(&self, rng: &mut impl Rng) -> Option<&Variables> {
+        let i = match self.order {
+            FeedOrder::Random => rng.random_range(0..self.rows.len()),
+            FeedOrder::Sequential | FeedOrder::Unique => {
+                let i = self.next.fetch_add(1, Ordering::Relaxed);
+                match self.end {
+                    FeedEnd::Recycle => i % self.rows.len(),
+                    FeedEnd::Stop if i < self.rows.len() => i,
+                    FeedEnd::Stop => {
+                        self.exhausted.store(true, Ordering::Relaxed);
+                        return None;
+                    }
+                }
+            }
+        };
+        Some(&self.rows[i])
+    }
+
+    /// Whether a virtual user was denied a row.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FeedEnd, FeedOrder, Feeder};
+    use crate::generator::rng;
+
+    #[test]
+    fn test_parse_csv() {
+        let content = "id,name,note\r\n1,joe,\n\n2,\"Doe, Jane\",\"said \"\"hi\"\"\nand left\"\n";
+        let rows = Feeder::parse_csv(content).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["name"], "joe");
+        assert_eq!(rows[0]["note"], "");
+        assert_eq!(rows[1]["name"], "Doe, Jane");
+        assert_eq!(rows[1]["note"], "said \"hi\"\nand left");
+        let error = Feeder::parse_csv("id,name\n1,joe\n2\n").unwrap_err();
+        assert_eq!(error, "line 3 has 1 fields instead of 2");
+        assert!(Feeder::parse_csv("").is_err());
+    }
+
+    #[test]
+    fn test_parse_ndjson() {
+        let content = "{\"id\": 1, \"name\": \"joe\"}\n\n{\"id\": 2, \"tags\": [\"a\"]}\n";
+        let rows = Feeder::parse_ndjson(content).unwrap();
+        assert_eq!(rows[0]["id"], "1");
+        assert_eq!(rows[0]["name"], "joe");
+        assert_eq!(rows[1]["tags"], r#"["a"]"#);
+        assert!(Feeder::parse_ndjson("[1, 2]").is_err());
+    }
+
+    #[test]
+    fn test_next_row() {
+        let rows = Feeder::parse_csv("id\n1\n2\n").unwrap();
+        let mut rng = rng(None);
+        let feeder = Feeder::new(rows.clone(), FeedOrder::Sequential, FeedEnd::Recycle).unwrap();
+        assert_eq!(feeder.columns(), ["id"]);
+        let ids = (0..3).map(|_| feeder.next_row(&mut rng).unwrap()["id"].as_str());
+        assert_eq!(ids.collect::<Vec<_>>(), ["1", "2", "1"]);
+        assert!(!feeder.is_exhausted());
+
+        let feeder = Feeder::new(rows.clone(), FeedOrder::Unique, FeedEnd::Stop).unwrap();
+        assert!(feeder.next_row(&mut rng).is_some());
+        assert!(feeder.next_row(&mut rng).is_some());
+        assert!(feeder.next_row(&mut rng).is_none());
+        assert!(feeder.is_exhausted());
+
+        let feeder = Feeder::new(rows, FeedOrder::Random, FeedEnd::Stop).unwrap();
+        assert!((0..10).all(|_| feeder.next_row(&mut rng).is_some()));
+        assert!(Feeder::new(Vec::new(), FeedOrder::Random, FeedEnd::Stop).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rand::{distr::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
+
+/// A built-in function of a template placeholder like `{{random_int(1, 100)}}`, which generates
+/// a new value for every request.
+#[derive(Debug, Clone)]
+pub enum Generator {
+    /// `random_int(min, max)`, both inclusive.
+    RandomInt(i64, i64),
+    /// `random_string(length)` of ASCII letters and digits.
+    RandomString(usize),
+    /// `uuid()`, a random (version 4) UUID.
+    Uuid,
+    /// `timestamp()` in seconds since the Unix epoch.
+    Timestamp,
+    /// `timestamp_ms()` in milliseconds since the Unix epoch.
+    TimestampMs,
+    /// `sequence()` or `sequence(start)`, counting up from `start` (default 1). The count is shared
+    /// by all virtual users, so every request gets a number of its own. Which user gets which
+    /// number depends on timing, also with a seed.
+    Sequence { start: u64, next: Arc<AtomicU64> },
+    /// `pick(a, b, c)`, one of the values at random.
+    Pick(Vec<String>),
+}
+
+impl Generator {
+    pub fn generate(&self, rng: &mut impl Rng) -> String {
+        match self {
+            Generator::RandomInt(min, max) => rng.random_range(*min..=*max).to_string(),
+            Generator::RandomString(length) => {
+                let chars = (0..*length).map(|_| rng.sample(Alphanumeric) as char);
+                chars.collect()
+            }
+            Generator::Uuid => {
+                let bits = rng.random::<u128>() & !(0xf000 << 64) & !(0xc << 60);
+                let bits = bits | 0x4000 << 64 | 0x8 << 60;
+                let hex = format!("{bits:032x}");
+                let groups = [
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..],
+                ];
+                groups.join("-")
+            }
+            Generator::Timestamp => since_epoch().as_secs().to_string(),
+            Generator::TimestampMs => since_epoch().as_millis().to_string(),
+            Generator::Sequence { next, .. } => next.fetch_add(1, Ordering::Relaxed).to_string(),
+            Generator::Pick(values) => values[rng.random_range(0..values.len())].clone(),
+        }
+    }
+
+    fn sequence(start: u64) -> Self {
+        let next = Arc::new(AtomicU64::new(start));
+        Generator::Sequence { start, next }
+    }
+
+    /// Counts a `sequence()` from its start again, e.g. for the next run.
+    pub fn restart(&self) {
+        if let Generator::Sequence { start, next } = self {
+            next.store(*start, Ordering::Relaxed);
+        }
+    }
+}
+
+fn since_epoch() -> std::time::Duration {
+    let now = SystemTime::now();
+    now.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+impl FromStr for Generator {
+    type Err = String;
+
+    /// Parses calls like `random_int(1, 100)`. The arguments are separated by commas, without
+    /// quotes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("`{s}` isn't a function call like `random_int(1, 100)`");
+        let (name, args) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(invalid)?;
+        let args = match args.trim() {
+            "" => Vec::new(),
+            args => args.split(',').map(str::trim).collect(),
+        };
+        let number = |arg: &str| {
+            arg.parse::<i64>()
+                .map_err(|_| format!("`{arg}` in `{s}` isn't a number"))
+        };
+        let generator = match (name.trim(), &args[..]) {
+            ("random_int", [min, max]) => {
+                let (min, max) = (number(min)?, number(max)?);
+                if min > max {
+                    return Err(format!("`{s}` has a minimum above its maximum"));
+                }
+                Generator::RandomInt(min, max)
+            }
+            ("random_string", [length]) => {
+                let length = length.parse().map_err(|_| invalid())?;
+                Generator::RandomString(length)
+            }
+            ("uuid", []) => Generator::Uuid,
+            ("timestamp", []) => Generator::Timestamp,
+            ("timestamp_ms", []) => Generator::TimestampMs,
+            ("sequence", []) => Generator::sequence(1),
+            ("sequence", [start]) => Generator::sequence(start.parse().map_err(|_| invalid())?),
+            ("pick", values) if !values.is_empty() => {
+                Generator::Pick(values.iter().map(|v| v.to_string()).collect())
+            }
+            (name, _) => {
+                return Err(format!(
+                    "`{name}` in `{s}` isn't a function or takes other arguments"
+                ))
+            }
+        };
+        Ok(generator)
+    }
+}
+
+impl fmt::Display for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Generator::RandomInt(min, max) => write!(f, "random_int({min}, {max})"),
+            Generator::RandomString(length) => write!(f, "random_string({length})"),
+            Generator::Uuid => write!(f, "uuid()"),
+            Generator::Timestamp => write!(f, "timestamp()"),
+            Generator::TimestampMs => write!(f, "timestamp_ms()"),
+            Generator::Sequence { start, .. } => write!(f, "sequence({start})"),
+            Generator::Pick(values) => write!(f, "pick({})", values.join(", ")),
+        }
+    }
+}
+
+/// The random numbers of a virtual user. With a `seed` they are the same in every run.
+pub fn rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(&mut rand::rng()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rng, Generator};
+
+    fn generate(call: &str, seed: u64) -> String {
+        let generator: Generator = call.parse().unwrap();
+        generator.generate(&mut rng(Some(seed)))
+    }
+
+    #[test]
+    fn test_generate() {
+        let n = generate("random_int(5, 7)", 1).parse::<i64>().unwrap();
+        assert!((5..=7).contains(&n));
+        let s = generate("random_string(12)", 1);
+        assert_eq!(s.len(), 12);
+        assert!(s.chars().all(|c| c.is_ascii_alphanumeric()));
+        let uuid = generate("uuid()", 1);
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+        assert!(["8", "9", "a", "b"].contains(&&uuid[19..20]));
+        assert!(["red", "green"].contains(&generate("pick(red, green)", 1).as_str()));
+        assert!(generate("timestamp_ms()", 1).len() >= 13);
+
+        let sequence: Generator = "sequence(10)".parse().unwrap();
+        let copy = sequence.clone();
+        let mut rng = rng(None);
+        assert_eq!(sequence.generate(&mut rng), "10");
+        assert_eq!(copy.generate(&mut rng), "11");
+        sequence.restart();
+        assert_eq!(copy.generate(&mut rng), "10");
+        assert_eq!(sequence.to_string(), "sequence(10)");
+    }
+
+    #[test]
+    fn test_seeded_values_repeat() {
+        for call in [
+            "random_int(0, 1000000)",
+            "random_string(16)",
+            "uuid()",
+            "pick(a, b, c, d)",
+        ] {
+            assert_eq!(generate(call, 42), generate(call, 42));
+        }
+        assert_ne!(generate("uuid()", 1), generate("uuid()", 2));
+    }
+
+    #[test]
+    fn test_invalid_calls() {
+        for call in [
+            "random_int(1)",
+            "random_int(9, 1)",
+            "random_int(a, b)",
+            "uuid(1)",
+            "pick()",
+            "now()",
+            "uuid(",
+        ] {
+            assert!(call.parse::<Generator>().is_err(), "{call}");
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::{collections::HashMap, hash::Hash, ops::RangeInclusive};
+
+use hdrhistogram::Histogram;
+
+use crate::{Phase, RequestReport};
+
+/// Number of significant figures kept when nothing else is configured.
+pub const DEFAULT_PRECISION: u8 = 3;
+pub const PRECISION_RANGE: RangeInclusive<u8> = 1..=4;
+
+/// Highest trackable latency (one hour). Larger values are clamped to it.
+const MAX_LATENCY_US: u64 = 3_600_000_000;
+
+/// Constant-memory latency recording (in microseconds) for the requests of one outcome.
+#[derive(Debug, Clone)]
+pub struct RequestHistograms {
+    pub duration: Histogram<u64>,
+    pub corrected_duration: Histogram<u64>,
+    /// In the order of `Phase::ALL`, only of the requests the phase was timed for. Created with
+    /// the first of them, as most connections never time the phases of opening one.
+    phases: [Option<Histogram<u64>>; Phase::ALL.len()],
+    /// Total bytes of the request bodies.
+    pub sent_bytes: u64,
+    /// Total bytes of the response bodies.
+    pub received_bytes: u64,
+    precision: u8,
+}
+
+/// An empty latency histogram with `precision` significant figures.
+pub fn latency_histogram(precision: u8) -> Histogram<u64> {
+    Histogram::new_with_bounds(1, MAX_LATENCY_US, precision)
+        .expect("The histogram precision is out of range")
+}
+
+impl RequestHistograms {
+    pub fn new(precision: u8) -> Self {
+        let histogram = latency_histogram(precision);
+        RequestHistograms {
+            duration: histogram.clone(),
+            corrected_duration: histogram,
+            phases: Default::default(),
+            sent_bytes: 0,
+            received_bytes: 0,
+            precision,
+        }
+    }
+
+    pub fn record(&mut self, report: &RequestReport) {
+        self.duration
+            .saturating_record(report.duration.as_micros() as u64);
+        self.corrected_duration
+            .saturating_record(report.corrected_duration.as_micros() as u64);
+        self.sent_bytes += report.sent_bytes;
+        self.received_bytes += report.received_bytes;
+        for (h, phase) in self.phases.iter_mut().zip(report.phases.to_array()) {
+            if let Some(phase) = phase {
+                h.get_or_insert_with(|| latency_histogram(self.precision))
+                    .saturating_record(phase.as_micros() as u64);
+            }
+        }
+    }
+
+    /// The histogram of the phase, `None` if no request timed it.
+    pub fn phase(&self, phase: Phase) -> Option<&Histogram<u64>> {
+        self.phases[phase as usize].as_ref()
+    }
+
+    pub fn add(&mut self, other: &RequestHistograms) {
+        // both sides are created with the same bounds, so this can't fail
+        self.duration
+            .add(&other.duration)
+            .expect("Histograms with different bounds");
+        self.corrected_duration
+            .add(&other.corrected_duration)
+            .expect("Histograms with different bounds");
+        self.sent_bytes += other.sent_bytes;
+        self.received_bytes += other.received_bytes;
+        for (h, other) in self.phases.iter_mut().zip(&other.phases) {
+            match (h, other) {
+                (Some(h), Some(other)) => h.add(other).expect("Histograms with different bounds"),
+                (h, Some(other)) => *h = Some(other.clone()),
+                (_, None) => {}
+            }
+        }
+    }
+}
+
+/// Merges the histograms of several connections by key, e.g. by outcome.
+pub fn merge<'a, K: Hash + Eq + Copy + 'a>(
+    histograms: impl IntoIterator<Item = &'a HashMap<K, RequestHistograms>>,
+) -> HashMap<K, RequestHistograms> {
+    let mut merged: HashMap<K, RequestHistograms> = HashMap::new();
+    for by_key in histograms {
+        for (key, h) in by_key {
+            match merged.get_mut(key) {
+                Some(m) => m.add(h),
+                None => {
+                    merged.insert(*key, h.clone());
+                }
+            }
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use crate::{Outcome, Phase, Phases, RequestReport};
+
+    use super::{merge, RequestHistograms, DEFAULT_PRECISION};
+
+    fn report(duration_ms: u64) -> RequestReport {
+        RequestReport {
+            outcome: Outcome::Status(200),
+            duration: Duration::from_millis(duration_ms),
+            corrected_duration: Duration::from_millis(duration_ms * 2),
+            phases: Phases::default(),
+            sent_bytes: 0,
+            received_bytes: duration_ms * 100,
+            headers: None,
+            body: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_by_status_code() {
+        let mut first = HashMap::new();
+        let mut h = RequestHistograms::new(DEFAULT_PRECISION);
+        h.record(&report(1));
+        first.insert(200, h);
+
+        let mut second = HashMap::new();
+        let mut h = RequestHistograms::new(DEFAULT_PRECISION);
+        h.record(&report(3));
+        second.insert(200, h.clone());
+        second.insert(500, h);
+
+        let merged = merge([&first, &second]);
+        assert_eq!(merged[&200].duration.len(), 2);
+        assert_eq!(merged[&200].duration.max(), 3001);
+        assert_eq!(merged[&200].corrected_duration.max(), 6003);
+        assert_eq!(merged[&200].received_bytes, 400);
+        assert_eq!(merged[&500].duration.len(), 1);
+        assert!(merged[&200].phase(Phase::Connect).is_none());
+    }
+
+    #[test]
+    fn test_phases_are_only_kept_once_timed() {
+        let mut h = RequestHistograms::new(DEFAULT_PRECISION);
+        h.record(&report(1));
+        assert!(Phase::ALL.iter().all(|&phase| h.phase(phase).is_none()));
+
+        let mut connected = report(2);
+        connected.phases.connect = Some(Duration::from_millis(1));
+        let mut other = RequestHistograms::new(DEFAULT_PRECISION);
+        other.record(&connected);
+        h.add(&other);
+        h.add(&other);
+        assert_eq!(h.phase(Phase::Connect).unwrap().len(), 2);
+        assert!(h.phase(Phase::Dns).is_none());
+    }
+}
@@ -0,0 +1,89 @@
+use std::{fmt, str::FromStr};
+
+use serde_json::Value;
+
+/// A path to a value in a JSON document, like `$.items[0].name`. The leading `$` is optional.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    path: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+impl JsonPath {
+    /// The value at the path, if there is one.
+    pub fn find<'a>(&self, document: &'a Value) -> Option<&'a Value> {
+        self.segments
+            .iter()
+            .try_fold(document, |value, segment| match segment {
+                Segment::Key(key) => value.get(key),
+                Segment::Index(index) => value.get(index),
+            })
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("`{s}` isn't a JSON path like `$.items[0].name`");
+        let rest = s.strip_prefix('$').unwrap_or(s);
+        let rest = rest.strip_prefix('.').unwrap_or(rest);
+        let mut segments = Vec::new();
+        for part in rest.split('.').filter(|p| !p.is_empty()) {
+            let (key, mut indexes) = part.split_once('[').map_or((part, ""), |(k, i)| (k, i));
+            if !key.is_empty() {
+                segments.push(Segment::Key(key.to_string()));
+            }
+            while !indexes.is_empty() {
+                let (index, next) = indexes.split_once(']').ok_or_else(invalid)?;
+                segments.push(Segment::Index(index.parse().map_err(|_| invalid())?));
+                indexes = match next {
+                    "" => "",
+                    next => next.strip_prefix('[').ok_or_else(invalid)?,
+                };
+            }
+        }
+        Ok(JsonPath {
+            path: s.to_string(),
+            segments,
+        })
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::JsonPath;
+
+    #[test]
+    fn test_find() {
+        let document = json!({"items": [{"name": "a"}, {"name": "b", "tags": [[1, 2]]}]});
+        let find = |path: &str| path.parse::<JsonPath>().unwrap().find(&document).cloned();
+        assert_eq!(find("$.items[1].name"), Some(json!("b")));
+        assert_eq!(find("items[0].name"), Some(json!("a")));
+        assert_eq!(find("$.items[1].tags[0][1]"), Some(json!(2)));
+        assert_eq!(find("$"), Some(document.clone()));
+        assert_eq!(find("$.items[2].name"), None);
+        assert_eq!(find("$.missing"), None);
+    }
+
+    #[test]
+    fn test_invalid_paths() {
+        for path in ["$.items[x]", "$.items[0", "$.items[0]x"] {
+            assert!(path.parse::<JsonPath>().is_err(), "{path}");
+        }
+    }
+}
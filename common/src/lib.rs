@@ -1,32 +1,170 @@
-use std::time::Duration;
+use std::{fmt, time::Duration};
 
 use anyhow::Result;
 use async_trait::async_trait;
 
-use hyper::{client::HttpConnector, Body, Client, Uri};
+use hyper::{client::HttpConnector, header::HeaderMap, Body, Client};
+use tokio::time::Instant;
 
 pub mod agent;
+pub mod assertion;
 pub mod becnhmark;
 pub mod cli;
+pub mod client;
+pub mod cookie;
+pub mod error;
+pub mod extract;
+pub mod feeder;
+pub mod generator;
+pub mod histogram;
+pub mod json_path;
+pub mod mix;
+pub mod profile;
+pub mod raw;
+pub mod request;
+pub mod scenario;
+pub mod status;
+pub mod template;
+pub mod tls;
 
 pub use becnhmark::do_request_raw;
+pub use client::{BenchmarkClient, Engine, HttpVersion, HyperClient, Timeouts};
+pub use error::ErrorKind;
+pub use request::RequestSpec;
+pub use status::StatusSet;
+pub use tls::{TlsConfig, TlsOptions};
 
 pub type HttpClient = Client<HttpConnector, Body>;
 
 #[async_trait]
-pub trait StatusOnlyHttpClient {
-    async fn get(&self, uri: Uri) -> Result<u16>;
+pub trait RequestSender: Sync {
+    async fn send(&self, request: &RequestSpec) -> Result<u16>;
+
+    /// Like `send`, and also tells what the client knows about the phases of the request and the
+    /// size of the response.
+    async fn send_with_stats(&self, request: &RequestSpec) -> (Result<u16>, RequestStats) {
+        (self.send(request).await, RequestStats::default())
+    }
 }
 
-#[async_trait]
-impl StatusOnlyHttpClient for HttpClient {
-    async fn get(&self, uri: Uri) -> Result<u16> {
-        Ok(self.get(uri).await?.status().into())
+/// What a client knows about a request besides its status code.
+#[derive(Debug, Clone, Default)]
+pub struct RequestStats {
+    /// When the request was written, if that was later than asked for, e.g. after opening a new
+    /// connection or waiting for a turn on a pipelined one.
+    pub written: Option<Instant>,
+    pub phases: Phases,
+    /// Bytes of the response body, as far as it was read.
+    pub received_bytes: u64,
+    /// The headers of a complete response, if the request asked to keep them.
+    pub headers: Option<HeaderMap>,
+    /// The body of a complete response, if the request asked to keep it.
+    pub body: Option<Vec<u8>>,
+    /// The first assertion of the request a response with an expected status failed.
+    pub failed_assertion: Option<error::AssertionError>,
+}
+
+/// How long the phases of a request took. Opening a connection is only timed for requests that
+/// needed a new one, and the response only for requests that got one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Phases {
+    /// Resolving the host name, unless the URI has an IP address.
+    pub dns: Option<Duration>,
+    /// Establishing the TCP connection.
+    pub connect: Option<Duration>,
+    /// The TLS handshake of `https` connections.
+    pub tls: Option<Duration>,
+    /// From writing the request until the response started (time to first byte).
+    pub ttfb: Option<Duration>,
+    /// Reading the rest of the response.
+    pub download: Option<Duration>,
+}
+
+impl Phases {
+    pub const NAMES: [&'static str; 5] = ["dns", "connect", "tls", "ttfb", "download"];
+
+    /// The phases in the order of `NAMES`.
+    pub fn to_array(&self) -> [Option<Duration>; 5] {
+        [self.dns, self.connect, self.tls, self.ttfb, self.download]
+    }
+
+    /// Opening the connection, before the request could be written.
+    pub fn connection(&self) -> Option<Duration> {
+        let phases = [self.dns, self.connect, self.tls];
+        phases.iter().flatten().copied().reduce(|a, b| a + b)
+    }
+}
+
+/// One of the `Phases`, in the order of `Phases::NAMES`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Dns,
+    Connect,
+    Tls,
+    Ttfb,
+    Download,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 5] = [
+        Phase::Dns,
+        Phase::Connect,
+        Phase::Tls,
+        Phase::Ttfb,
+        Phase::Download,
+    ];
+}
+
+/// What became of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Outcome {
+    Status(u16),
+    /// The response had an expected status but failed an assertion of the request.
+    AssertionFailed,
+    Error(ErrorKind),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Status(status_code) => write!(f, "{status_code}"),
+            Outcome::AssertionFailed => write!(f, "assertion_failed"),
+            Outcome::Error(kind) => write!(f, "{kind}"),
+        }
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct RequestReport {
-    pub status_code: u16,
+    pub outcome: Outcome,
+    /// Time from actually sending the request until the response arrived.
     pub duration: Duration,
+    /// Time from when the request should have been sent until the response arrived.
+    pub corrected_duration: Duration,
+    /// Opening a new connection isn't part of `duration`, only of `corrected_duration`.
+    pub phases: Phases,
+    /// Bytes of the request body.
+    pub sent_bytes: u64,
+    /// Bytes of the response body.
+    pub received_bytes: u64,
+    /// The headers of the response, if the request asked to keep them.
+    pub headers: Option<HeaderMap>,
+    /// The body of the response, if the request asked to keep it.
+    pub body: Option<Vec<u8>>,
+}
+
+/// Parses durations like `500ms`, `30s`, `10m` or `1h`. A bare number is taken as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (amount, unit) = s.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("`{s}` isn't a duration like `10m`"))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(amount)),
+        "" | "s" => Ok(Duration::from_secs(amount)),
+        "m" => Ok(Duration::from_secs(amount * 60)),
+        "h" => Ok(Duration::from_secs(amount * 3600)),
+        _ => Err(format!("`{s}` isn't a duration like `10m`")),
+    }
 }
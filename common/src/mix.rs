@@ -0,0 +1,300 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use hyper::{
+    header::{HeaderName, HeaderValue},
+    Method, Uri,
+};
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::{template::RequestTemplate, RequestSpec};
+
+/// A named request of a traffic mix.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    pub name: String,
+    /// How often the endpoint is picked, relative to the weights of the others.
+    pub weight: u32,
+    pub request: RequestSpec,
+}
+
+/// The requests of a benchmark. Every request goes to one of the endpoints, picked at random in
+/// proportion to their weights.
+#[derive(Debug, Clone)]
+pub struct RequestMix {
+    endpoints: Vec<Endpoint>,
+    /// The requests of the endpoints, with their `{{variable}}` placeholders.
+    templates: Vec<RequestTemplate>,
+    /// The sum of the weights up to and including every endpoint.
+    cumulative_weights: Vec<u64>,
+}
+
+impl RequestMix {
+    pub fn new(endpoints: Vec<Endpoint>) -> Result<Self, String> {
+        if endpoints.is_empty() {
+            return Err("no endpoints".to_string());
+        }
+        if let Some(e) = endpoints.iter().find(|e| e.weight == 0) {
+            return Err(format!("the weight of `{}` must be positive", e.name));
+        }
+        let cumulative_weights = endpoints
+            .iter()
+            .scan(0, |sum, e| {
+                *sum += e.weight as u64;
+                Some(*sum)
+            })
+            .collect();
+        let templates = endpoints.iter().map(|e| e.request.clone().into()).collect();
+        Ok(RequestMix {
+            endpoints,
+            templates,
+            cumulative_weights,
+        })
+    }
+
+    /// Parses a JSON list of endpoints like
+    /// `[{"name": "read", "weight": 7, "url": "/items"}, {"name": "write", "method": "POST", ...}]`.
+    /// See `RequestDefinition` for the fields. The requests get the headers, expected statuses
+    /// and assertions of `base`, and relative URLs are resolved against its URI.
+    pub fn parse(content: &str, base: &RequestSpec) -> Result<Self, String> {
+        let definitions: Vec<RequestDefinition> =
+            serde_json::from_str(content).map_err(|e| e.to_string())?;
+        let endpoints = definitions
+            .iter()
+            .map(|d| d.to_endpoint(base))
+            .collect::<Result<Vec<_>, _>>()?;
+        RequestMix::new(endpoints)
+    }
+
+    pub fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    /// Whether there is more than one endpoint to pick from.
+    pub fn is_mixed(&self) -> bool {
+        self.endpoints.len() > 1
+    }
+
+    /// The names of the variables the requests need.
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.templates.iter().flat_map(RequestTemplate::variables)
+    }
+
+    /// Counts the `sequence()`s of the requests from their start again, e.g. for the next run.
+    pub fn restart_sequences(&self) {
+        self.templates
+            .iter()
+            .for_each(RequestTemplate::restart_sequences);
+    }
+
+    /// Picks the endpoint of the next request with `rng`, returns its index and request.
+    pub fn pick(&self, rng: &mut impl Rng) -> (usize, &RequestTemplate) {
+        let i = match self.is_mixed() {
+            true => {
+                let total = self.cumulative_weights[self.cumulative_weights.len() - 1];
+                let n = rng.random_range(0..total);
+                self.cumulative_weights.partition_point(|&w| w <= n)
+            }
+            false => 0,
+        };
+        (i, &self.templates[i])
+    }
+
+    /// The URI the TLS configuration is made for: the first `https` one, if there is any.
+    pub fn tls_uri(&self) -> &Uri {
+        let uris = self.endpoints.iter().map(|e| &e.request.uri);
+        let mut https = uris.clone().filter(|u| u.scheme_str() == Some("https"));
+        https.next().unwrap_or(&self.endpoints[0].request.uri)
+    }
+}
+
+impl From<RequestSpec> for RequestMix {
+    /// A mix of only this request.
+    fn from(request: RequestSpec) -> Self {
+        let endpoint = Endpoint {
+            name: request.uri.path().to_string(),
+            weight: 1,
+            request: request.clone(),
+        };
+        RequestMix {
+            endpoints: vec![endpoint],
+            templates: vec![request.into()],
+            cumulative_weights: vec![1],
+        }
+    }
+}
+
+/// A request as written in an endpoints or scenario file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RequestDefinition {
+    /// Defaults to the URL.
+    pub name: Option<String>,
+    /// Only for endpoints, defaults to 1.
+    pub weight: Option<u32>,
+    /// Defaults to `GET`.
+    pub method: Option<String>,
+    /// Absolute, or a path on the host of the base request.
+    pub url: String,
+    /// Added to the headers of the base request.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: String,
+    /// Replaces the expected statuses of the base request, e.g. `201` or `2xx,404`.
+    pub expect: Option<String>,
+    /// Only for scenario steps, the pause before the next step, e.g. `500ms`.
+    pub think_time: Option<String>,
+    /// Only for scenario steps, the variables to take from the response, e.g.
+    /// `{"token": {"json": "$.token"}}`.
+    #[serde(default)]
+    pub extract: BTreeMap<String, ExtractDefinition>,
+}
+
+/// Where an extractor of a scenario step finds its value.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub(crate) enum ExtractDefinition {
+    /// A JSON path like `$.items[0].id`.
+    Json(String),
+    /// A regex, of which the first capture group is taken if it has one.
+    Regex(String),
+    /// A header name.
+    Header(String),
+}
+
+impl RequestDefinition {
+    /// Returns the name and the request, which gets the headers, expected statuses and
+    /// assertions of `base`.
+    pub(crate) fn to_request(&self, base: &RequestSpec) -> Result<(String, RequestSpec), String> {
+        let uri = resolve(&base.uri, &self.url)?;
+        let method = match &self.method {
+            Some(method) => Method::from_str(&method.to_uppercase())
+                .map_err(|_| format!("`{method}` isn't an HTTP method"))?,
+            None => Method::GET,
+        };
+        let mut request = RequestSpec::new(method, uri);
+        request.headers = base.headers.clone();
+        for (name, value) in &self.headers {
+            let invalid = || format!("`{name}: {value}` isn't a valid header");
+            let name = HeaderName::from_str(name).map_err(|_| invalid())?;
+            let value = HeaderValue::from_str(value).map_err(|_| invalid())?;
+            request.headers.insert(name, value);
+        }
+        request.body = self.body.clone().into();
+        request.expected_status = match &self.expect {
+            Some(expect) => expect.parse()?,
+            None => base.expected_status.clone(),
+        };
+        request.assertions = base.assertions.clone();
+        let name = self.name.clone().unwrap_or_else(|| self.url.clone());
+        Ok((name, request))
+    }
+
+    fn to_endpoint(&self, base: &RequestSpec) -> Result<Endpoint, String> {
+        let (name, request) = self.to_request(base)?;
+        if self.think_time.is_some() || !self.extract.is_empty() {
+            return Err(format!(
+                "`{name}` is an endpoint, only scenario steps have a think time or extractors"
+            ));
+        }
+        Ok(Endpoint {
+            name,
+            weight: self.weight.unwrap_or(1),
+            request,
+        })
+    }
+}
+
+/// Resolves a path like `/items?page=2` against the scheme and host of `base`, absolute URLs are
+/// kept.
+fn resolve(base: &Uri, url: &str) -> Result<Uri, String> {
+    let invalid = |e: hyper::http::Error| format!("`{url}` isn't a valid URL: {e}");
+    let uri = Uri::from_str(url).map_err(|e| invalid(e.into()))?;
+    if uri.scheme().is_some() || !url.starts_with('/') {
+        return Ok(uri);
+    }
+    let mut builder = Uri::builder().path_and_query(url);
+    if let Some(scheme) = base.scheme() {
+        builder = builder.scheme(scheme.clone());
+    }
+    if let Some(authority) = base.authority() {
+        builder = builder.authority(authority.clone());
+    }
+    builder.build().map_err(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header::CONTENT_TYPE, Method, Uri};
+
+    use super::RequestMix;
+    use crate::{generator::rng, RequestSpec};
+
+    fn base() -> RequestSpec {
+        let mut base = RequestSpec::get(Uri::from_static("http://dummy:8080/"));
+        base.headers
+            .insert("authorization", "Bearer token".parse().unwrap());
+        base
+    }
+
+    #[test]
+    fn test_parse() {
+        let content = r#"[
+            {"name": "read", "weight": 7, "url": "/items?page=2"},
+            {"url": "https://other/search", "expect": "2xx,404"},
+            {
+                "name": "write",
+                "weight": 2,
+                "method": "post",
+                "url": "/items",
+                "headers": {"content-type": "application/json"},
+                "body": "{}",
+                "expect": "201"
+            }
+        ]"#;
+        let mix = RequestMix::parse(content, &base()).unwrap();
+        let [read, search, write] = mix.endpoints() else {
+            panic!("expected three endpoints");
+        };
+        assert_eq!((read.name.as_str(), read.weight), ("read", 7));
+        assert_eq!(read.request.uri, "http://dummy:8080/items?page=2");
+        assert_eq!(read.request.headers["authorization"], "Bearer token");
+        assert_eq!(search.name, "https://other/search");
+        assert_eq!(search.weight, 1);
+        assert!(search.request.expected_status.contains(404));
+        assert_eq!(write.request.method, Method::POST);
+        assert_eq!(write.request.headers[CONTENT_TYPE], "application/json");
+        assert_eq!(write.request.body, "{}");
+        assert!(!write.request.expected_status.contains(200));
+        assert_eq!(mix.tls_uri(), "https://other/search");
+    }
+
+    #[test]
+    fn test_invalid_endpoints() {
+        assert!(RequestMix::parse("[]", &base()).is_err());
+        assert!(RequestMix::parse(r#"[{"url": "/", "weight": 0}]"#, &base()).is_err());
+        assert!(RequestMix::parse(r#"[{"url": "/", "method": "GET GET"}]"#, &base()).is_err());
+        assert!(RequestMix::parse(r#"[{"url": "/", "verb": "GET"}]"#, &base()).is_err());
+        assert!(RequestMix::parse(r#"[{"url": "/", "expect": "abc"}]"#, &base()).is_err());
+        assert!(RequestMix::parse(r#"[{"url": "/", "think_time": "1s"}]"#, &base()).is_err());
+    }
+
+    #[test]
+    fn test_pick_follows_the_weights() {
+        let content = r#"[
+            {"weight": 7, "url": "/read"},
+            {"weight": 2, "url": "/search"},
+            {"weight": 1, "url": "/write"}
+        ]"#;
+        let mix = RequestMix::parse(content, &base()).unwrap();
+        let mut rng = rng(None);
+        let mut counts = [0i32; 3];
+        for _ in 0..10_000 {
+            counts[mix.pick(&mut rng).0] += 1;
+        }
+        for (count, expected) in counts.into_iter().zip([7_000, 2_000, 1_000]) {
+            assert!((count - expected).abs() < 300, "{counts:?}");
+        }
+    }
+}
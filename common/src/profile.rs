@@ -0,0 +1,164 @@
+use std::{str::FromStr, time::Duration};
+
+use tokio::{
+    sync::watch,
+    time::{interval, Instant},
+};
+
+use crate::parse_duration;
+
+/// How often the number of active connections is recalculated while ramping.
+const UPDATE_INTERVAL_MS: u64 = 100;
+
+/// Moves linearly from the previous number of connections to `target` within `duration`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stage {
+    pub duration: Duration,
+    pub target: u64,
+}
+
+impl FromStr for Stage {
+    type Err = String;
+
+    /// Parses stages in the form `duration:target`, e.g. `2m:500`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (duration, target) = s
+            .split_once(':')
+            .ok_or(format!("`{s}` isn't a stage like `2m:500`"))?;
+        Ok(Stage {
+            duration: parse_duration(duration.trim())?,
+            target: target
+                .trim()
+                .parse()
+                .map_err(|_| format!("`{target}` isn't a number of connections"))?,
+        })
+    }
+}
+
+/// A sequence of stages, starting at `start` connections.
+#[derive(Debug, Clone)]
+pub struct LoadProfile {
+    pub start: u64,
+    pub stages: Vec<Stage>,
+}
+
+/// Where a running profile is at a given time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfileState {
+    pub stage: usize,
+    pub connections: u64,
+}
+
+impl LoadProfile {
+    pub fn new(start: u64, stages: Vec<Stage>) -> Self {
+        LoadProfile { start, stages }
+    }
+
+    /// Parses a profile file with one `duration:target` stage per line. Empty lines and lines
+    /// starting with `#` are ignored.
+    pub fn parse(start: u64, content: &str) -> Result<Self, String> {
+        let stages = content
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(Stage::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(LoadProfile::new(start, stages))
+    }
+
+    /// The number of connections needed at the peak of the profile.
+    pub fn max_connections(&self) -> u64 {
+        self.stages
+            .iter()
+            .map(|s| s.target)
+            .fold(self.start, u64::max)
+    }
+
+    pub fn total_duration(&self) -> Duration {
+        self.stages.iter().map(|s| s.duration).sum()
+    }
+
+    /// The state `elapsed` after the start, or `None` once the last stage is over.
+    pub fn state_at(&self, elapsed: Duration) -> Option<ProfileState> {
+        let mut from = self.start;
+        let mut stage_start = Duration::ZERO;
+        for (stage, s) in self.stages.iter().enumerate() {
+            let stage_end = stage_start + s.duration;
+            if elapsed < stage_end {
+                let progress = (elapsed - stage_start).as_secs_f64() / s.duration.as_secs_f64();
+                let connections = from as f64 + (s.target as f64 - from as f64) * progress;
+                return Some(ProfileState {
+                    stage,
+                    connections: connections.round() as u64,
+                });
+            }
+            from = s.target;
+            stage_start = stage_end;
+        }
+        None
+    }
+
+    /// Publishes the current state until the profile is over. The last state is kept afterwards.
+    pub async fn drive(&self, start_instant: Instant, tx_state: watch::Sender<ProfileState>) {
+        let mut interval = interval(Duration::from_millis(UPDATE_INTERVAL_MS));
+        loop {
+            interval.tick().await;
+            let Some(state) = self.state_at(start_instant.elapsed()) else {
+                return;
+            };
+            tx_state.send_if_modified(|current| {
+                let modified = *current != state;
+                *current = state;
+                modified
+            });
+            if tx_state.is_closed() {
+                return;
+            }
+        }
+    }
+
+    pub fn initial_state(&self) -> ProfileState {
+        ProfileState {
+            stage: 0,
+            connections: self.start,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{LoadProfile, ProfileState, Stage};
+
+    fn profile() -> LoadProfile {
+        LoadProfile::parse(10, "# ramp up\n2m:500\n10m:500\n\n1m:0\n").unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let profile = profile();
+        assert_eq!(
+            profile.stages[0],
+            Stage {
+                duration: Duration::from_secs(120),
+                target: 500
+            }
+        );
+        assert_eq!(profile.stages.len(), 3);
+        assert_eq!(profile.max_connections(), 500);
+        assert_eq!(profile.total_duration(), Duration::from_secs(780));
+        assert!(LoadProfile::parse(10, "2m").is_err());
+    }
+
+    #[test]
+    fn test_state_at() {
+        let profile = profile();
+        let state = |stage, connections| Some(ProfileState { stage, connections });
+        assert_eq!(profile.state_at(Duration::ZERO), state(0, 10));
+        assert_eq!(profile.state_at(Duration::from_secs(60)), state(0, 255));
+        assert_eq!(profile.state_at(Duration::from_secs(300)), state(1, 500));
+        assert_eq!(profile.state_at(Duration::from_secs(750)), state(2, 250));
+        assert_eq!(profile.state_at(Duration::from_secs(780)), None);
+    }
+}
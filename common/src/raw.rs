@@ -0,0 +1,740 @@
+use std::{future::Future, io, net::IpAddr, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use hyper::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Method,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
+    net::{
+        lookup_host,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    select,
+    sync::{mpsc, oneshot, Mutex, Notify},
+    time::{error::Elapsed, timeout, Instant},
+};
+
+use crate::{
+    assertion::Assertion,
+    error::{PipelineClosedError, ProtocolError},
+    Phases, RequestSender, RequestSpec, RequestStats, Timeouts,
+};
+
+/// A minimal HTTP/1.1 client on a single keep-alive TCP connection, without the overhead of hyper.
+/// Clones share the connection, so their concurrent requests are pipelined: written one after the
+/// other and answered in the same order. Their latency is measured from the write, not from the
+/// wait for their turn.
+#[derive(Clone)]
+pub struct RawClient {
+    connection: Arc<Mutex<Option<Connection>>>,
+    timeouts: Timeouts,
+    requests_per_connection: Option<u64>,
+}
+
+/// The write side of an open connection. The read side belongs to a task reading the responses.
+struct Connection {
+    authority: String,
+    writer: OwnedWriteHalf,
+    tx_pending: mpsc::UnboundedSender<PendingResponse>,
+    /// Requests written so far.
+    requests: u64,
+    /// Tells the reading task to give up on the pending responses.
+    closed: Arc<Notify>,
+}
+
+/// A request that was written and waits for its response.
+struct PendingResponse {
+    /// `false` for `HEAD` requests, whose responses announce a body without sending it.
+    has_body: bool,
+    /// Whether the headers are needed, to check assertions or hand them back.
+    keep_headers: bool,
+    /// Whether the body is needed, to check assertions or hand it back.
+    keep_body: bool,
+    written: Instant,
+    tx_response: oneshot::Sender<(Result<RawResponse>, RequestStats)>,
+}
+
+impl RawClient {
+    /// Opens a new connection after `requests_per_connection` requests, if given.
+    pub fn new(timeouts: Timeouts, requests_per_connection: Option<u64>) -> Self {
+        RawClient {
+            connection: Arc::new(Mutex::new(None)),
+            timeouts,
+            requests_per_connection,
+        }
+    }
+
+    /// Writes the request, (re)connecting if necessary. Returns where the response will arrive,
+    /// and the `closed` signal of the connection it was written to. When the request was written
+    /// goes into `stats`.
+    async fn write(
+        &self,
+        request: &RequestSpec,
+        stats: &mut RequestStats,
+    ) -> Result<(
+        oneshot::Receiver<(Result<RawResponse>, RequestStats)>,
+        Arc<Notify>,
+    )> {
+        let authority = request
+            .uri
+            .authority()
+            .context("The URI has no host")?
+            .to_string();
+        let bytes = request.to_http1_bytes()?;
+
+        let mut connection = self.connection.lock().await;
+        let open = match connection.take() {
+            Some(c)
+                if c.authority == authority
+                    && !c.tx_pending.is_closed()
+                    && self.requests_per_connection.is_none_or(|n| c.requests < n) =>
+            {
+                c
+            }
+            // a replaced connection is closed once its pending responses are read
+            _ => Connection::open(authority, &self.timeouts, &mut stats.phases).await?,
+        };
+        let open = connection.insert(open);
+        open.requests += 1;
+        let written = Instant::now();
+        stats.written = Some(written);
+        if let Err(e) = open.writer.write_all(&bytes).await {
+            open.closed.notify_one();
+            *connection = None;
+            return Err(e.into());
+        }
+        let (tx_response, rx_response) = oneshot::channel();
+        let pending = PendingResponse {
+            has_body: request.method != Method::HEAD,
+            keep_headers: !request.assertions.is_empty() || request.keep_headers,
+            keep_body: request.assertions.iter().any(Assertion::needs_body) || request.keep_body,
+            written,
+            tx_response,
+        };
+        if open.tx_pending.send(pending).is_err() {
+            // the connection ended while this request was written behind others
+            *connection = None;
+            return Err(PipelineClosedError.into());
+        }
+        Ok((rx_response, open.closed.clone()))
+    }
+
+    /// Closes the connection with the `closed` signal, unless it was already replaced, and fails
+    /// the requests pipelined on it. The next request opens a new one.
+    async fn close(&self, closed: &Arc<Notify>) {
+        let mut connection = self.connection.lock().await;
+        if connection
+            .as_ref()
+            .is_some_and(|c| Arc::ptr_eq(&c.closed, closed))
+        {
+            *connection = None;
+        }
+        closed.notify_one();
+    }
+}
+
+impl Connection {
+    /// Times resolving the host name (unless it's an IP address) and connecting in `phases`.
+    async fn open(authority: String, timeouts: &Timeouts, phases: &mut Phases) -> Result<Self> {
+        let connect = async {
+            let start_instant = Instant::now();
+            let address = with_default_port(&authority);
+            let addresses = lookup_host(&address).await?.collect::<Vec<_>>();
+            let host = address.rsplit_once(':').map_or("", |(host, _)| host);
+            let is_ip = host.trim_matches(['[', ']']).parse::<IpAddr>().is_ok();
+            if !is_ip {
+                phases.dns = Some(start_instant.elapsed());
+            }
+            let start_instant = Instant::now();
+            let stream = TcpStream::connect(&addresses[..]).await?;
+            phases.connect = Some(start_instant.elapsed());
+            Ok::<_, io::Error>(stream)
+        };
+        let stream = match timeouts.connect {
+            Some(limit) => timeout(limit, connect).await??,
+            None => connect.await?,
+        };
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+        let (tx_pending, rx_pending) = mpsc::unbounded_channel();
+        let reader = ResponseReader::new(reader, timeouts.idle_body);
+        let closed = Arc::new(Notify::new());
+        tokio::spawn(read_responses(reader, rx_pending, closed.clone()));
+        Ok(Connection {
+            authority,
+            writer,
+            tx_pending,
+            requests: 0,
+            closed,
+        })
+    }
+}
+
+fn with_default_port(authority: &str) -> String {
+    match authority.rsplit_once(':') {
+        Some((_, port)) if !port.ends_with(']') => authority.to_string(),
+        _ => format!("{authority}:80"),
+    }
+}
+
+/// Answers the pending requests of a connection in order, until the connection can't be reused or
+/// is `closed`. Requests that are still pending then fail with a `PipelineClosedError`.
+async fn read_responses(
+    mut reader: ResponseReader<OwnedReadHalf>,
+    mut rx_pending: mpsc::UnboundedReceiver<PendingResponse>,
+    closed: Arc<Notify>,
+) {
+    while let Some(pending) = rx_pending.recv().await {
+        let read = reader.read_response(pending.has_body, pending.keep_headers, pending.keep_body);
+        let response = select! {
+            response = read => response,
+            _ = closed.notified() => Err(PipelineClosedError.into()),
+        };
+        let mut stats = RequestStats {
+            received_bytes: reader.body_bytes(),
+            ..RequestStats::default()
+        };
+        if let Some(first_byte) = reader.first_byte() {
+            let phases = &mut stats.phases;
+            phases.ttfb = Some(first_byte.saturating_duration_since(pending.written));
+            phases.download = response.is_ok().then(|| first_byte.elapsed());
+        }
+        let keep_alive = response.as_ref().is_ok_and(|r| r.keep_alive);
+        if !keep_alive {
+            // before answering, so that the next request goes to a new connection
+            rx_pending.close();
+        }
+        let _ = pending.tx_response.send((response, stats));
+        if !keep_alive {
+            break;
+        }
+    }
+    while let Ok(pending) = rx_pending.try_recv() {
+        let closed = Err(PipelineClosedError.into());
+        let _ = pending.tx_response.send((closed, RequestStats::default()));
+    }
+}
+
+#[async_trait]
+impl RequestSender for RawClient {
+    async fn send(&self, request: &RequestSpec) -> Result<u16> {
+        self.send_with_stats(request).await.0
+    }
+
+    async fn send_with_stats(&self, request: &RequestSpec) -> (Result<u16>, RequestStats) {
+        let mut stats = RequestStats::default();
+        let mut connection = None;
+        let response = async {
+            let (rx_response, closed) = self.write(request, &mut stats).await?;
+            connection = Some(closed);
+            let (response, response_stats) = rx_response
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            stats.phases.ttfb = response_stats.phases.ttfb;
+            stats.phases.download = response_stats.phases.download;
+            stats.received_bytes = response_stats.received_bytes;
+            let response = response?;
+            if request.expected_status.contains(response.status) {
+                let (headers, body) = (&response.headers, &response.body);
+                let body_size = response_stats.received_bytes;
+                let checked = Assertion::check_all(&request.assertions, headers, body, body_size);
+                stats.failed_assertion = checked.err();
+            }
+            stats.headers = request.keep_headers.then_some(response.headers);
+            stats.body = request.keep_body.then_some(response.body);
+            Ok(response.status)
+        };
+        let status = match self.timeouts.request {
+            Some(limit) => timeout(limit, response).await.map_err(anyhow::Error::from),
+            None => Ok(response.await),
+        };
+        let status = status.and_then(|s| s);
+        if let (Err(e), Some(closed)) = (&status, &connection) {
+            // a response that didn't come in time or in one piece would hold up the ones behind it
+            if e.is::<Elapsed>() || e.is::<io::Error>() {
+                self.close(closed).await;
+            }
+        }
+        (status, stats)
+    }
+}
+
+/// The parts of a response the engine cares about. The headers and body are only kept if asked
+/// for, and discarded otherwise.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RawResponse {
+    pub status: u16,
+    /// Whether the connection can be used for the next response.
+    pub keep_alive: bool,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// How the end of a response body is found.
+enum BodyLength {
+    Empty,
+    Fixed(u64),
+    Chunked,
+    UntilClose,
+}
+
+/// Reads consecutive HTTP/1.x responses from a connection.
+pub struct ResponseReader<R> {
+    reader: BufReader<R>,
+    line: Vec<u8>,
+    /// When the current response started to arrive.
+    first_byte: Option<Instant>,
+    /// Bytes of the current response body, without the chunked framing.
+    body_bytes: u64,
+    /// The current response body, if it's kept.
+    kept_body: Option<Vec<u8>>,
+    /// The longest pause between two reads.
+    idle_timeout: Option<Duration>,
+}
+
+impl<R: AsyncRead + Unpin> ResponseReader<R> {
+    pub fn new(reader: R, idle_timeout: Option<Duration>) -> Self {
+        ResponseReader {
+            reader: BufReader::new(reader),
+            line: Vec::new(),
+            first_byte: None,
+            body_bytes: 0,
+            kept_body: None,
+            idle_timeout,
+        }
+    }
+
+    /// Reads the next final response, skipping informational (`1xx`) ones. Keeps its headers if
+    /// `keep_headers` and its body if `keep_body`.
+    pub async fn read_response(
+        &mut self,
+        has_body: bool,
+        keep_headers: bool,
+        keep_body: bool,
+    ) -> Result<RawResponse> {
+        self.first_byte = None;
+        self.body_bytes = 0;
+        self.kept_body = keep_body.then(Vec::new);
+        loop {
+            let status_line = self.read_line().await?;
+            let (is_http11, status) = parse_status_line(status_line)?;
+            self.first_byte.get_or_insert_with(Instant::now);
+            // HTTP/1.0 closes the connection unless asked otherwise
+            let mut keep_alive = is_http11;
+            let mut content_length = None;
+            let mut chunked = false;
+            let mut headers = HeaderMap::new();
+            loop {
+                let line = self.read_line().await?;
+                if line.is_empty() {
+                    break;
+                }
+                let (name, value) = std::str::from_utf8(line)
+                    .ok()
+                    .and_then(|l| l.split_once(':'))
+                    .ok_or_else(|| ProtocolError::new("invalid header"))?;
+                let value = value.trim();
+                if keep_headers {
+                    let name = HeaderName::from_bytes(name.as_bytes());
+                    let value = HeaderValue::from_str(value);
+                    if let (Ok(name), Ok(value)) = (name, value) {
+                        headers.append(name, value);
+                    }
+                }
+                if name.eq_ignore_ascii_case("content-length") {
+                    let length = value
+                        .parse::<u64>()
+                        .map_err(|_| ProtocolError::new("invalid content-length"))?;
+                    content_length = Some(length);
+                } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                    chunked = value.to_ascii_lowercase().ends_with("chunked");
+                } else if name.eq_ignore_ascii_case("connection") {
+                    let value = value.to_ascii_lowercase();
+                    keep_alive = match value.as_str() {
+                        "close" => false,
+                        "keep-alive" => true,
+                        _ => keep_alive,
+                    };
+                }
+            }
+            if (100..200).contains(&status) && status != 101 {
+                continue;
+            }
+
+            let body = match content_length {
+                _ if !has_body || status == 204 || status == 304 => BodyLength::Empty,
+                _ if chunked => BodyLength::Chunked,
+                Some(length) => BodyLength::Fixed(length),
+                None => BodyLength::UntilClose,
+            };
+            match body {
+                BodyLength::Empty => {}
+                BodyLength::Fixed(length) => self.skip(length).await?,
+                BodyLength::Chunked => self.skip_chunks().await?,
+                BodyLength::UntilClose => {
+                    self.skip_to_end().await?;
+                    keep_alive = false;
+                }
+            }
+            let body = self.kept_body.take().unwrap_or_default();
+            return Ok(RawResponse {
+                status,
+                keep_alive,
+                headers,
+                body,
+            });
+        }
+    }
+
+    /// When the status line of the last response was read.
+    pub fn first_byte(&self) -> Option<Instant> {
+        self.first_byte
+    }
+
+    /// Bytes of the body of the last response, as far as it was read.
+    pub fn body_bytes(&self) -> u64 {
+        self.body_bytes
+    }
+
+    /// Reads a line without its line break. Fails at the end of the stream.
+    async fn read_line(&mut self) -> Result<&[u8]> {
+        self.line.clear();
+        let read = self.reader.read_until(b'\n', &mut self.line);
+        let n = with_idle_timeout(self.idle_timeout, read).await?;
+        if n == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let line = self.line.strip_suffix(b"\n").unwrap_or(&self.line);
+        Ok(line.strip_suffix(b"\r").unwrap_or(line))
+    }
+
+    async fn skip(&mut self, mut length: u64) -> Result<()> {
+        while length > 0 {
+            let buf = with_idle_timeout(self.idle_timeout, self.reader.fill_buf()).await?;
+            if buf.is_empty() {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            let n = buf.len().min(length as usize);
+            if let Some(kept) = &mut self.kept_body {
+                kept.extend_from_slice(&buf[..n]);
+            }
+            self.reader.consume(n);
+            self.body_bytes += n as u64;
+            length -= n as u64;
+        }
+        Ok(())
+    }
+
+    async fn skip_chunks(&mut self) -> Result<()> {
+        loop {
+            let line = self.read_line().await?;
+            let size = std::str::from_utf8(line)
+                .ok()
+                .map(|l| l.split(';').next().unwrap_or_default().trim())
+                .and_then(|size| u64::from_str_radix(size, 16).ok())
+                .ok_or_else(|| ProtocolError::new("invalid chunk size"))?;
+            if size == 0 {
+                // skip the trailers
+                while !self.read_line().await?.is_empty() {}
+                return Ok(());
+            }
+            self.skip(size).await?;
+            if !self.read_line().await?.is_empty() {
+                return Err(ProtocolError::new("missing line break after chunk").into());
+            }
+        }
+    }
+
+    async fn skip_to_end(&mut self) -> Result<()> {
+        loop {
+            let buf = with_idle_timeout(self.idle_timeout, self.reader.fill_buf()).await?;
+            if buf.is_empty() {
+                return Ok(());
+            }
+            let n = buf.len();
+            if let Some(kept) = &mut self.kept_body {
+                kept.extend_from_slice(buf);
+            }
+            self.reader.consume(n);
+            self.body_bytes += n as u64;
+        }
+    }
+}
+
+/// Returns whether the response is HTTP/1.1 (or HTTP/1.0) and its status code.
+fn parse_status_line(line: &[u8]) -> Result<(bool, u16)> {
+    let invalid = || ProtocolError::new("invalid status line");
+    let line = std::str::from_utf8(line).map_err(|_| invalid())?;
+    let mut parts = line.splitn(3, ' ');
+    let version = parts
+        .next()
+        .filter(|v| v.starts_with("HTTP/1."))
+        .ok_or_else(invalid)?;
+    let status = parts
+        .next()
+        .and_then(|s| s.parse::<u16>().ok())
+        .filter(|s| (100..=999).contains(s))
+        .ok_or_else(invalid)?;
+    Ok((version == "HTTP/1.1", status))
+}
+
+async fn with_idle_timeout<T>(
+    limit: Option<Duration>,
+    read: impl Future<Output = io::Result<T>>,
+) -> Result<T> {
+    match limit {
+        Some(limit) => Ok(timeout(limit, read).await??),
+        None => Ok(read.await?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use hyper::{header::HeaderMap, server::conn::Http, service::service_fn, Body, Response, Uri};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{RawClient, RawResponse, ResponseReader};
+    use crate::{ErrorKind, RequestSender, RequestSpec, Timeouts};
+
+    async fn read_all(mut input: &[u8], has_body: bool) -> Vec<anyhow::Result<RawResponse>> {
+        let mut reader = ResponseReader::new(&mut input, None);
+        let mut responses = Vec::new();
+        loop {
+            let response = reader.read_response(has_body, false, false).await;
+            let done = !response.as_ref().is_ok_and(|r| r.keep_alive);
+            responses.push(response);
+            if done {
+                return responses;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reads_content_length_and_chunked_bodies() {
+        let input = b"HTTP/1.1 100 Continue\r\n\r\n\
+            HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello\
+            HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\ntrailer: x\r\n\r\n\
+            HTTP/1.1 204 No Content\r\n\r\n\
+            HTTP/1.0 500 Internal Server Error\r\n\r\nuntil the end";
+        let responses = read_all(input, true).await;
+        let statuses = responses
+            .iter()
+            .map(|r| r.as_ref().unwrap().status)
+            .collect::<Vec<_>>();
+        assert_eq!(statuses, [200, 404, 204, 500]);
+        assert!(!responses[3].as_ref().unwrap().keep_alive);
+    }
+
+    #[tokio::test]
+    async fn test_counts_body_bytes_without_chunk_framing() {
+        let mut input = &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"[..];
+        let mut reader = ResponseReader::new(&mut input, None);
+        reader.read_response(true, false, false).await.unwrap();
+        assert_eq!(reader.body_bytes(), 11);
+    }
+
+    #[tokio::test]
+    async fn test_keeps_the_headers_and_body_if_asked() {
+        let input = b"HTTP/1.1 200 OK\r\nset-cookie: id=1\r\ncontent-length: 5\r\n\r\nhello";
+        let read = |keep_headers, keep_body| async move {
+            let mut input = &input[..];
+            let mut reader = ResponseReader::new(&mut input, None);
+            let response = reader.read_response(true, keep_headers, keep_body).await;
+            response.unwrap()
+        };
+        let response = read(true, false).await;
+        assert_eq!(response.headers["set-cookie"], "id=1");
+        assert!(response.body.is_empty());
+        let response = read(false, true).await;
+        assert!(response.headers.is_empty());
+        assert_eq!(response.body, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_head_response_has_no_body() {
+        let input = b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\nconnection: close\r\n\r\n";
+        let responses = read_all(input, false).await;
+        assert_eq!(
+            responses[0].as_ref().unwrap(),
+            &RawResponse {
+                status: 200,
+                keep_alive: false,
+                headers: HeaderMap::new(),
+                body: Vec::new(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_and_truncated_responses() {
+        let responses = read_all(b"SSH-2.0-OpenSSH\r\n", true).await;
+        let error = responses[0].as_ref().unwrap_err();
+        assert_eq!(ErrorKind::classify(error), ErrorKind::Protocol);
+        let responses = read_all(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhel", true).await;
+        let error = responses[0].as_ref().unwrap_err();
+        assert_eq!(ErrorKind::classify(error), ErrorKind::ConnectionReset);
+    }
+
+    /// Serves `200 OK` over HTTP/1.1. Returns the URI and the number of accepted connections.
+    async fn server() -> (Uri, Arc<AtomicU64>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}/path?query", listener.local_addr().unwrap());
+        let accepted = Arc::new(AtomicU64::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                let service = service_fn(|request| async move {
+                    assert_eq!(request.uri(), "/path?query");
+                    Ok::<_, hyper::Error>(Response::new(Body::from("hello")))
+                });
+                tokio::spawn(
+                    Http::new()
+                        .http1_only(true)
+                        .serve_connection(stream, service),
+                );
+            }
+        });
+        (uri.parse().unwrap(), accepted)
+    }
+
+    #[tokio::test]
+    async fn test_reuses_the_connection() {
+        let (uri, accepted) = server().await;
+        let client = RawClient::new(Timeouts::default(), None);
+        let request = RequestSpec::get(uri);
+        for _ in 0..10 {
+            assert_eq!(client.send(&request).await.unwrap(), 200);
+        }
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_times_the_phases() {
+        let (uri, _) = server().await;
+        let client = RawClient::new(Timeouts::default(), None);
+        let request = RequestSpec::get(uri);
+
+        let (status, stats) = client.send_with_stats(&request).await;
+        assert_eq!(status.unwrap(), 200);
+        let phases = stats.phases;
+        // the URI has an IP address
+        assert_eq!((phases.dns, phases.tls), (None, None));
+        assert!(phases.connect.is_some() && phases.ttfb.is_some() && phases.download.is_some());
+        assert_eq!(stats.received_bytes, 5);
+
+        let (_, stats) = client.send_with_stats(&request).await;
+        assert_eq!(stats.phases.connect, None);
+        assert!(stats.phases.ttfb.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_pipelines_concurrent_requests() {
+        let (uri, accepted) = server().await;
+        let client = RawClient::new(Timeouts::default(), None);
+        let request = RequestSpec::get(uri);
+        let handles = (0..8)
+            .map(|_| {
+                let (client, request) = (client.clone(), request.clone());
+                tokio::spawn(async move { client.send(&request).await.unwrap() })
+            })
+            .collect::<Vec<_>>();
+        for h in handles {
+            assert_eq!(h.await.unwrap(), 200);
+        }
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_connection_closed_mid_pipeline() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // answer the first of four pipelined requests, then close
+            let mut received = Vec::new();
+            while received.windows(4).filter(|w| w == b"\r\n\r\n").count() < 4 {
+                let mut buf = [0; 1024];
+                let n = stream.read(&mut buf).await.unwrap();
+                received.extend_from_slice(&buf[..n]);
+            }
+            let response = b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+            stream.write_all(response).await.unwrap();
+        });
+
+        let client = RawClient::new(Timeouts::default(), None);
+        let request = RequestSpec::get(uri.parse().unwrap());
+        let handles = (0..4)
+            .map(|_| {
+                let (client, request) = (client.clone(), request.clone());
+                tokio::spawn(async move { client.send(&request).await })
+            })
+            .collect::<Vec<_>>();
+        let mut outcomes = Vec::new();
+        for h in handles {
+            outcomes.push(h.await.unwrap().map_err(|e| ErrorKind::classify(&e)));
+        }
+        outcomes.sort();
+        let closed = Err(ErrorKind::PipelineClosed);
+        assert_eq!(outcomes, [Ok(200), closed, closed, closed]);
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_after_a_hung_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}/", listener.local_addr().unwrap());
+        let accepted = Arc::new(AtomicU64::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            // the first connection reads the requests but never answers
+            let (mut hung, _) = listener.accept().await.unwrap();
+            counter.fetch_add(1, Ordering::Relaxed);
+            tokio::spawn(async move { while hung.read(&mut [0; 1024]).await.unwrap_or(0) > 0 {} });
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                let service =
+                    service_fn(|_| async { Ok::<_, hyper::Error>(Response::new(Body::empty())) });
+                tokio::spawn(Http::new().serve_connection(stream, service));
+            }
+        });
+
+        let timeouts = Timeouts {
+            request: Some(Duration::from_millis(200)),
+            ..Timeouts::default()
+        };
+        let client = RawClient::new(timeouts, None);
+        let request = RequestSpec::get(uri.parse().unwrap());
+        let first = {
+            let (client, request) = (client.clone(), request.clone());
+            tokio::spawn(async move { client.send(&request).await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // pipelined behind the first one, fails when that one times out
+        let second = client
+            .send(&request)
+            .await
+            .map_err(|e| ErrorKind::classify(&e));
+        let first = first.await.unwrap().map_err(|e| ErrorKind::classify(&e));
+        assert_eq!(first, Err(ErrorKind::Timeout));
+        assert_eq!(second, Err(ErrorKind::PipelineClosed));
+        assert_eq!(client.send(&request).await.unwrap(), 200);
+        assert_eq!(accepted.load(Ordering::Relaxed), 2);
+    }
+}
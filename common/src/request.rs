@@ -0,0 +1,112 @@
+use anyhow::Context;
+use hyper::{
+    body::Bytes,
+    header::{HeaderMap, CONTENT_LENGTH, HOST},
+    Body, Method, Request, Uri,
+};
+
+use crate::{assertion::Assertion, StatusSet};
+
+/// Describes the request that is sent over and over again by a benchmark.
+#[derive(Debug, Clone)]
+pub struct RequestSpec {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// Responses with other status codes count as failed.
+    pub expected_status: StatusSet,
+    /// Checked on responses with an expected status, a response failing one counts as its own outcome.
+    pub assertions: Vec<Assertion>,
+    /// Whether the client hands back the headers of the response, e.g. for cookies.
+    pub keep_headers: bool,
+    /// Whether the client hands back the body of the response, e.g. for extractors.
+    pub keep_body: bool,
+}
+
+impl RequestSpec {
+    pub fn new(method: Method, uri: Uri) -> Self {
+        RequestSpec {
+            method,
+            uri,
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+            expected_status: StatusSet::default(),
+            assertions: Vec::new(),
+            keep_headers: false,
+            keep_body: false,
+        }
+    }
+
+    pub fn get(uri: Uri) -> Self {
+        RequestSpec::new(Method::GET, uri)
+    }
+
+    pub fn to_hyper_request(&self) -> anyhow::Result<Request<Body>> {
+        let mut request = Request::builder()
+            .method(self.method.clone())
+            .uri(self.uri.clone())
+            .body(Body::from(self.body.clone()))?;
+        request.headers_mut().extend(self.headers.clone());
+        Ok(request)
+    }
+
+    /// Serializes the request as HTTP/1.1, for the raw engine.
+    pub fn to_http1_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let path = self.uri.path_and_query().map_or("/", |p| p.as_str());
+        let mut bytes = format!("{} {path} HTTP/1.1\r\n", self.method).into_bytes();
+        if !self.headers.contains_key(HOST) {
+            let host = self.uri.authority().context("The URI has no host")?;
+            bytes.extend_from_slice(format!("host: {host}\r\n").as_bytes());
+        }
+        for (name, value) in &self.headers {
+            bytes.extend_from_slice(name.as_str().as_bytes());
+            bytes.extend_from_slice(b": ");
+            bytes.extend_from_slice(value.as_bytes());
+            bytes.extend_from_slice(b"\r\n");
+        }
+        let sends_body = !self.body.is_empty()
+            || [Method::POST, Method::PUT, Method::PATCH].contains(&self.method);
+        if sends_body && !self.headers.contains_key(CONTENT_LENGTH) {
+            bytes.extend_from_slice(format!("content-length: {}\r\n", self.body.len()).as_bytes());
+        }
+        bytes.extend_from_slice(b"\r\n");
+        bytes.extend_from_slice(&self.body);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{body, header::CONTENT_TYPE, Method, Uri};
+
+    use super::RequestSpec;
+
+    #[tokio::test]
+    async fn test_to_hyper_request() {
+        let mut spec = RequestSpec::new(Method::POST, Uri::from_static("http://dummy/person"));
+        spec.headers
+            .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        spec.body = r#"{"name":"joshua"}"#.into();
+
+        let request = spec.to_hyper_request().unwrap();
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.uri(), "http://dummy/person");
+        assert_eq!(request.headers()[CONTENT_TYPE], "application/json");
+        let body = body::to_bytes(request.into_body()).await.unwrap();
+        assert_eq!(body, r#"{"name":"joshua"}"#);
+    }
+
+    #[test]
+    fn test_to_http1_bytes() {
+        let mut spec = RequestSpec::new(Method::POST, Uri::from_static("http://dummy:8080/p?q=1"));
+        spec.headers
+            .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        spec.body = "{}".into();
+        let bytes = spec.to_http1_bytes().unwrap();
+        assert_eq!(
+            std::str::from_utf8(&bytes).unwrap(),
+            "POST /p?q=1 HTTP/1.1\r\nhost: dummy:8080\r\ncontent-type: application/json\r\ncontent-length: 2\r\n\r\n{}"
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use std::{collections::HashSet, str::FromStr, time::Duration};
+
+use hyper::header::HeaderName;
+use regex::bytes::Regex;
+
+use crate::{
+    extract::{Extractor, Source},
+    mix::{ExtractDefinition, RequestDefinition},
+    parse_duration,
+    template::RequestTemplate,
+    RequestSpec,
+};
+
+/// A step of a scenario.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub name: String,
+    pub request: RequestTemplate,
+    /// The pause after the step, before the next one.
+    pub think_time: Duration,
+    /// Take values from the response for the requests of later steps. The step fails if one of
+    /// them isn't found.
+    pub extractors: Vec<Extractor>,
+}
+
+/// A flow that every virtual user runs over and over, e.g. login, list the cart, add an item and
+/// check out. An iteration stops at the first step that fails.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub steps: Vec<Step>,
+}
+
+impl Scenario {
+    /// Parses a JSON list of steps like
+    /// `[{"name": "login", "method": "POST", "url": "/login", "think_time": "1s"}, ...]`, with the
+    /// fields of an endpoint (see `RequestMix::parse`) except the weight. The URL, header values
+    /// and body can use `{{variable}}`s that earlier steps `extract`, e.g.
+    /// `"extract": {"token": {"json": "$.token"}, "csrf": {"regex": "..."}, "id": {"header": "x-id"}}`,
+    /// or the `columns` of a feeder.
+    pub fn parse(content: &str, base: &RequestSpec, columns: &[String]) -> Result<Self, String> {
+        let definitions: Vec<RequestDefinition> =
+            serde_json::from_str(content).map_err(|e| e.to_string())?;
+        let mut extracted = columns.iter().cloned().collect::<HashSet<_>>();
+        let mut steps = Vec::with_capacity(definitions.len());
+        for definition in &definitions {
+            let step = Step::parse(definition, base)?;
+            if let Some(name) = step.request.variables().find(|&v| !extracted.contains(v)) {
+                return Err(format!(
+                    "`{{{{{name}}}}}` in `{}` isn't extracted by an earlier step or fed",
+                    step.name
+                ));
+            }
+            extracted.extend(step.extractors.iter().map(|e| e.variable.clone()));
+            steps.push(step);
+        }
+        if steps.is_empty() {
+            return Err("no steps".to_string());
+        }
+        Ok(Scenario { steps })
+    }
+
+    /// Counts the `sequence()`s of the steps from their start again, e.g. for the next run.
+    pub fn restart_sequences(&self) {
+        self.steps
+            .iter()
+            .for_each(|s| s.request.restart_sequences());
+    }
+}
+
+impl Step {
+    fn parse(definition: &RequestDefinition, base: &RequestSpec) -> Result<Self, String> {
+        let mut request = RequestTemplate::new(definition, base)?;
+        let name = definition.name.clone();
+        let name = name.unwrap_or_else(|| definition.url.clone());
+        if definition.weight.is_some() {
+            return Err(format!("`{name}` is a step, only endpoints have a weight"));
+        }
+        let think_time = definition.think_time.as_deref().map(parse_duration);
+        let extractors = definition
+            .extract
+            .iter()
+            .map(|(variable, extract)| {
+                let source = match extract {
+                    ExtractDefinition::Json(path) => Source::Json(path.parse()?),
+                    ExtractDefinition::Regex(regex) => Source::Regex(
+                        Regex::new(regex).map_err(|e| format!("`{regex}` isn't a regex: {e}"))?,
+                    ),
+                    ExtractDefinition::Header(name) => Source::Header(
+                        HeaderName::from_str(name)
+                            .map_err(|_| format!("`{name}` isn't a header name"))?,
+                    ),
+                };
+                let variable = variable.clone();
+                Ok(Extractor { variable, source })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let from_headers = |e: &Extractor| matches!(e.source, Source::Header(_));
+        let headers = extractors.iter().any(from_headers);
+        let body = extractors.iter().any(|e| !from_headers(e));
+        request.keep_response(headers, body);
+        Ok(Step {
+            name,
+            request,
+            think_time: think_time.transpose()?.unwrap_or_default(),
+            extractors,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hyper::{header::AUTHORIZATION, Method, Uri};
+
+    use super::Scenario;
+    use crate::{generator::rng, template::Variables, RequestSpec};
+
+    #[test]
+    fn test_parse() {
+        let content = r#"[
+            {"name": "login", "method": "POST", "url": "/login", "think_time": "500ms"},
+            {"name": "cart", "url": "/cart", "think_time": "2s"},
+            {"name": "checkout", "method": "POST", "url": "/checkout", "expect": "201"}
+        ]"#;
+        let base = RequestSpec::get(Uri::from_static("http://dummy/"));
+        let scenario = Scenario::parse(content, &base, &[]).unwrap();
+        let names = scenario.steps.iter().map(|s| s.name.as_str());
+        assert_eq!(names.collect::<Vec<_>>(), ["login", "cart", "checkout"]);
+        let (variables, mut rng) = (Variables::new(), rng(None));
+        let login = scenario.steps[0]
+            .request
+            .render(&variables, &mut rng)
+            .unwrap();
+        assert_eq!(login.method, Method::POST);
+        let cart = scenario.steps[1]
+            .request
+            .render(&variables, &mut rng)
+            .unwrap();
+        assert_eq!(cart.uri, "http://dummy/cart");
+        assert_eq!(scenario.steps[1].think_time, Duration::from_secs(2));
+        assert_eq!(scenario.steps[2].think_time, Duration::ZERO);
+        assert!(!scenario.steps[2].request.expected_status().contains(200));
+    }
+
+    #[test]
+    fn test_variables_of_earlier_steps() {
+        let content = r#"[
+            {
+                "name": "login",
+                "method": "POST",
+                "url": "/login",
+                "extract": {"token": {"json": "$.token"}, "user": {"header": "x-user"}}
+            },
+            {
+                "name": "item",
+                "method": "PUT",
+                "url": "/users/{{user}}/items",
+                "headers": {"authorization": "Bearer {{token}}"},
+                "body": "{\"owner\": \"{{ user }}\"}"
+            }
+        ]"#;
+        let base = RequestSpec::get(Uri::from_static("http://dummy/"));
+        let scenario = Scenario::parse(content, &base, &[]).unwrap();
+        let mut rng = rng(None);
+        let login = scenario.steps[0]
+            .request
+            .render(&Variables::new(), &mut rng);
+        let login = login.unwrap();
+        assert!(login.keep_headers && login.keep_body);
+        let item = &scenario.steps[1].request;
+        assert_eq!(item.to_string(), "PUT /users/{{user}}/items");
+        assert!(item.render(&Variables::new(), &mut rng).is_err());
+        let variables = Variables::from([
+            ("token".to_string(), "abc".to_string()),
+            ("user".to_string(), "7".to_string()),
+        ]);
+        let item = item.render(&variables, &mut rng).unwrap();
+        assert_eq!(item.uri, "http://dummy/users/7/items");
+        assert_eq!(item.headers[AUTHORIZATION], "Bearer abc");
+        assert_eq!(item.body, r#"{"owner": "7"}"#);
+        assert!(!item.keep_headers && !item.keep_body);
+
+        let content = r#"[{"url": "/", "extract": {"id": {"header": "x-id"}}}]"#;
+        let scenario = Scenario::parse(content, &base, &[]).unwrap();
+        let request = scenario.steps[0].request.render(&variables, &mut rng);
+        let request = request.unwrap();
+        assert!(request.keep_headers && !request.keep_body);
+    }
+
+    #[test]
+    fn test_invalid_steps() {
+        let base = RequestSpec::get(Uri::from_static("http://dummy/"));
+        assert!(Scenario::parse("[]", &base, &[]).is_err());
+        assert!(Scenario::parse(r#"[{"url": "/", "weight": 2}]"#, &base, &[]).is_err());
+        assert!(Scenario::parse(r#"[{"url": "/", "think_time": "soon"}]"#, &base, &[]).is_err());
+        assert!(Scenario::parse(r#"[{"url": "/{{id}}"}]"#, &base, &[]).is_err());
+        let columns = ["id".to_string()];
+        assert!(Scenario::parse(r#"[{"url": "/{{id}}"}]"#, &base, &columns).is_ok());
+        let content = r#"[{"url": "/", "extract": {"id": {"xpath": "//id"}}}]"#;
+        assert!(Scenario::parse(content, &base, &[]).is_err());
+        let content = r#"[{"url": "/", "extract": {"id": {"regex": "("}}}]"#;
+        assert!(Scenario::parse(content, &base, &[]).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use std::{fmt, ops::RangeInclusive, str::FromStr};
+
+/// The status codes that count as a successful response, e.g. `2xx,304`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusSet(Vec<RangeInclusive<u16>>);
+
+impl StatusSet {
+    pub fn contains(&self, status_code: u16) -> bool {
+        self.0.iter().any(|r| r.contains(&status_code))
+    }
+}
+
+impl Default for StatusSet {
+    /// Any `2xx` or `3xx` status.
+    fn default() -> Self {
+        StatusSet(vec![200..=299, 300..=399])
+    }
+}
+
+impl FromStr for StatusSet {
+    type Err = String;
+
+    /// Parses a comma separated list of codes (`304`), classes (`2xx`) and ranges (`200-204`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |item: &str| format!("`{item}` isn't a status code, class or range");
+        let code = |c: &str| match c.trim().parse::<u16>() {
+            Ok(code) if (100..=599).contains(&code) => Ok(code),
+            _ => Err(invalid(c)),
+        };
+        s.split(',')
+            .map(|item| {
+                let item = item.trim();
+                if let Some(class) = item.strip_suffix("xx") {
+                    let class = code(&format!("{class}00"))?;
+                    Ok(class..=class + 99)
+                } else if let Some((from, to)) = item.split_once('-') {
+                    let (from, to) = (code(from)?, code(to)?);
+                    if from > to {
+                        return Err(format!("`{item}` is a range that ends before it starts"));
+                    }
+                    Ok(from..=to)
+                } else {
+                    let code = code(item)?;
+                    Ok(code..=code)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(StatusSet)
+    }
+}
+
+impl fmt::Display for StatusSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let items = self
+            .0
+            .iter()
+            .map(|r| match (r.start(), r.end()) {
+                (from, to) if from == to => from.to_string(),
+                (from, to) if from % 100 == 0 && *to == from + 99 => format!("{}xx", from / 100),
+                (from, to) => format!("{from}-{to}"),
+            })
+            .collect::<Vec<_>>();
+        f.write_str(&items.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StatusSet;
+
+    #[test]
+    fn test_parse_and_contains() {
+        let set: StatusSet = "2xx, 304,400-404".parse().unwrap();
+        assert!(set.contains(200));
+        assert!(set.contains(299));
+        assert!(set.contains(304));
+        assert!(set.contains(402));
+        assert!(!set.contains(301));
+        assert!(!set.contains(500));
+        assert_eq!(set.to_string(), "2xx,304,400-404");
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!("".parse::<StatusSet>().is_err());
+        assert!("9xx".parse::<StatusSet>().is_err());
+        assert!("ok".parse::<StatusSet>().is_err());
+        assert!("500-200".parse::<StatusSet>().is_err());
+        assert!("200-200".parse::<StatusSet>().is_ok());
+    }
+
+    #[test]
+    fn test_default() {
+        let set = StatusSet::default();
+        assert!(set.contains(302));
+        assert!(!set.contains(404));
+        assert_eq!(set.to_string(), "2xx,3xx");
+    }
+}
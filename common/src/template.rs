@@ -0,0 +1,371 @@
+use std::{borrow::Cow, collections::HashMap, fmt, str::FromStr};
+
+use rand::Rng;
+
+use crate::{generator::Generator, mix::RequestDefinition, RequestSpec, StatusSet};
+
+/// The variables of a virtual user, by name.
+pub type Variables = HashMap<String, String>;
+
+/// A text with `{{variable}}` placeholders, e.g. `/items/{{id}}`. Placeholders can also call a
+/// built-in function like `{{uuid()}}`, see `Generator`.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Variable(String),
+    Function(Generator),
+}
+
+impl Template {
+    /// A text without placeholders, even if it has `{{`.
+    fn literal(text: &str) -> Self {
+        Template {
+            parts: vec![Part::Text(text.to_string())],
+        }
+    }
+
+    /// Whether the text has no placeholders.
+    pub fn is_static(&self) -> bool {
+        self.parts.iter().all(|p| matches!(p, Part::Text(_)))
+    }
+
+    /// The names of the variables the placeholders refer to.
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|p| match p {
+            Part::Variable(name) => Some(name.as_str()),
+            Part::Text(_) | Part::Function(_) => None,
+        })
+    }
+
+    /// Counts the `sequence()`s from their start again.
+    pub fn restart_sequences(&self) {
+        for part in &self.parts {
+            if let Part::Function(generator) = part {
+                generator.restart();
+            }
+        }
+    }
+
+    /// Fills in the placeholders, failing on variables that aren't set. The functions take their
+    /// random values from `rng`.
+    pub fn render(&self, variables: &Variables, rng: &mut impl Rng) -> Result<String, String> {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Variable(name) => {
+                    let value = variables
+                        .get(name)
+                        .ok_or_else(|| format!("the variable `{name}` isn't set"))?;
+                    rendered.push_str(value);
+                }
+                Part::Function(generator) => rendered.push_str(&generator.generate(rng)),
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| format!("`{s}` has a `{{{{` without `}}}}`"))?;
+            let name = rest[start + 2..start + end].trim();
+            let valid = |c: char| c.is_ascii_alphanumeric() || c == '_';
+            let part = if name.contains('(') {
+                Part::Function(name.parse()?)
+            } else if !name.is_empty() && name.chars().all(valid) {
+                Part::Variable(name.to_string())
+            } else {
+                return Err(format!("`{name}` in `{s}` isn't a variable name"));
+            };
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            parts.push(part);
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Template { parts })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => write!(f, "{text}")?,
+                Part::Variable(name) => write!(f, "{{{{{name}}}}}")?,
+                Part::Function(generator) => write!(f, "{{{{{generator}}}}}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A request which may have `{{variable}}` and `{{function()}}` placeholders in its URL, header
+/// values and body. Those are rendered for every request.
+#[derive(Debug, Clone)]
+pub struct RequestTemplate {
+    /// The request itself without placeholders, otherwise the base the rendered requests get
+    /// their headers, expected statuses and assertions from.
+    request: RequestSpec,
+    templates: Option<Box<Templates>>,
+}
+
+#[derive(Debug, Clone)]
+struct Templates {
+    definition: RequestDefinition,
+    url: Template,
+    headers: Vec<(String, Template)>,
+    /// `None` keeps the body of the base request.
+    body: Option<Template>,
+}
+
+impl RequestTemplate {
+    /// The request of an endpoint or step definition, see `RequestDefinition::to_request`.
+    pub(crate) fn new(definition: &RequestDefinition, base: &RequestSpec) -> Result<Self, String> {
+        let url = Template::from_str(&definition.url)?;
+        let headers = definition
+            .headers
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), Template::from_str(value)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let body = Some(Template::from_str(&definition.body)?);
+        let templates = Templates {
+            definition: definition.clone(),
+            url,
+            headers,
+            body,
+        };
+        if templates.all().all(Template::is_static) {
+            let (_, request) = definition.to_request(base)?;
+            return Ok(RequestTemplate {
+                request,
+                templates: None,
+            });
+        }
+        let mut request = base.clone();
+        if let Some(expect) = &definition.expect {
+            request.expected_status = expect.parse()?;
+        }
+        Ok(RequestTemplate {
+            request,
+            templates: Some(Box::new(templates)),
+        })
+    }
+
+    /// Whether the client should hand back the headers and body of the rendered requests.
+    pub(crate) fn keep_response(&mut self, headers: bool, body: bool) {
+        self.request.keep_headers = headers;
+        self.request.keep_body = body;
+    }
+
+    /// The names of the variables the request needs.
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        let templates = self.templates.iter().flat_map(|t| t.all());
+        templates.flat_map(Template::variables)
+    }
+
+    /// Counts the `sequence()`s from their start again.
+    pub fn restart_sequences(&self) {
+        let templates = self.templates.iter().flat_map(|t| t.all());
+        templates.for_each(Template::restart_sequences);
+    }
+
+    pub fn expected_status(&self) -> &StatusSet {
+        &self.request.expected_status
+    }
+
+    /// Fills in the variables of a virtual user, and the values of the functions from its `rng`.
+    pub fn render(
+        &self,
+        variables: &Variables,
+        rng: &mut impl Rng,
+    ) -> Result<Cow<'_, RequestSpec>, String> {
+        let Some(templates) = &self.templates else {
+            return Ok(Cow::Borrowed(&self.request));
+        };
+        let mut definition = templates.definition.clone();
+        definition.url = templates.url.render(variables, rng)?;
+        for (name, value) in &templates.headers {
+            let value = value.render(variables, rng)?;
+            definition.headers.insert(name.clone(), value);
+        }
+        if let Some(body) = &templates.body {
+            definition.body = body.render(variables, rng)?;
+        }
+        let (_, mut request) = definition.to_request(&self.request)?;
+        if templates.body.is_none() {
+            request.body = self.request.body.clone();
+        }
+        request.keep_headers = self.request.keep_headers;
+        request.keep_body = self.request.keep_body;
+        Ok(Cow::Owned(request))
+    }
+}
+
+impl Templates {
+    fn all(&self) -> impl Iterator<Item = &Template> {
+        let headers = self.headers.iter().map(|(_, value)| value);
+        let url = std::iter::once(&self.url);
+        url.chain(headers).chain(self.body.as_ref())
+    }
+}
+
+impl From<RequestSpec> for RequestTemplate {
+    /// Finds the placeholders in the URI, header values and UTF-8 body of the request. Texts with
+    /// a `{{` that isn't a placeholder are taken as they are.
+    fn from(request: RequestSpec) -> Self {
+        let parse = |s: &str| Template::from_str(s).ok().filter(|t| !t.is_static());
+        let uri = request.uri.to_string();
+        let url = parse(&uri);
+        let headers = request
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), parse(value.to_str().ok()?)?)))
+            .collect::<Vec<_>>();
+        let body = std::str::from_utf8(&request.body).ok().and_then(parse);
+        if url.is_none() && headers.is_empty() && body.is_none() {
+            return RequestTemplate {
+                request,
+                templates: None,
+            };
+        }
+        let definition = RequestDefinition {
+            method: Some(request.method.to_string()),
+            url: uri.clone(),
+            ..RequestDefinition::default()
+        };
+        let templates = Templates {
+            definition,
+            url: url.unwrap_or_else(|| Template::literal(&uri)),
+            headers,
+            body,
+        };
+        RequestTemplate {
+            request,
+            templates: Some(Box::new(templates)),
+        }
+    }
+}
+
+impl fmt::Display for RequestTemplate {
+    /// The method and URL, with the placeholders.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.templates {
+            None => write!(f, "{} {}", self.request.method, self.request.uri),
+            Some(templates) => {
+                let method = templates.definition.method.as_deref().unwrap_or("GET");
+                write!(f, "{} {}", method.to_uppercase(), templates.url)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header::AUTHORIZATION, Method, Uri};
+
+    use super::{RequestTemplate, Template, Variables};
+    use crate::{generator::rng, RequestSpec};
+
+    #[test]
+    fn test_render() {
+        let template: Template = "/users/{{user_id}}/items/{{ id }}?v=1".parse().unwrap();
+        assert!(!template.is_static());
+        assert_eq!(template.variables().collect::<Vec<_>>(), ["user_id", "id"]);
+        let variables = Variables::from([
+            ("user_id".to_string(), "7".to_string()),
+            ("id".to_string(), "abc".to_string()),
+        ]);
+        let mut rng = rng(None);
+        assert_eq!(
+            template.render(&variables, &mut rng).unwrap(),
+            "/users/7/items/abc?v=1"
+        );
+        assert_eq!(template.to_string(), "/users/{{user_id}}/items/{{id}}?v=1");
+        let error = template.render(&Variables::new(), &mut rng).unwrap_err();
+        assert_eq!(error, "the variable `user_id` isn't set");
+    }
+
+    #[test]
+    fn test_static_and_invalid_templates() {
+        let template: Template = r#"{"name": "joe"}"#.parse().unwrap();
+        assert!(template.is_static());
+        assert_eq!(
+            template.render(&Variables::new(), &mut rng(None)).unwrap(),
+            r#"{"name": "joe"}"#
+        );
+        assert!("{{id".parse::<Template>().is_err());
+        assert!("{{}}".parse::<Template>().is_err());
+        assert!("{{a b}}".parse::<Template>().is_err());
+        assert!("{{random_int(1)}}".parse::<Template>().is_err());
+    }
+
+    #[test]
+    fn test_render_functions() {
+        let template: Template =
+            "/items/{{ random_int(1, 1000) }}?ref={{uuid()}}&n={{sequence(5)}}"
+                .parse()
+                .unwrap();
+        assert!(!template.is_static());
+        assert_eq!(template.variables().count(), 0);
+        assert_eq!(
+            template.to_string(),
+            "/items/{{random_int(1, 1000)}}?ref={{uuid()}}&n={{sequence(5)}}"
+        );
+        let first = template
+            .render(&Variables::new(), &mut rng(Some(7)))
+            .unwrap();
+        let second = template
+            .render(&Variables::new(), &mut rng(Some(7)))
+            .unwrap();
+        assert!(first.ends_with("&n=5"));
+        assert!(second.ends_with("&n=6"));
+        assert_eq!(first[..first.len() - 1], second[..second.len() - 1]);
+    }
+
+    #[test]
+    fn test_request_template_from_spec() {
+        let mut spec = RequestSpec::new(Method::PUT, Uri::from_static("http://dummy/p/{{id}}"));
+        spec.headers
+            .insert(AUTHORIZATION, "Bearer {{token}}".parse().unwrap());
+        spec.body = r#"{"name": "{{name}}", "tags": [{{}}]}"#.into();
+        spec.expected_status = "204".parse().unwrap();
+        let template = RequestTemplate::from(spec);
+        let mut variables = template.variables().collect::<Vec<_>>();
+        variables.sort();
+        assert_eq!(variables, ["id", "token"]);
+        assert_eq!(template.to_string(), "PUT http://dummy/p/{{id}}");
+        let variables = Variables::from([
+            ("id".to_string(), "7".to_string()),
+            ("token".to_string(), "abc".to_string()),
+        ]);
+        let request = template.render(&variables, &mut rng(None)).unwrap();
+        assert_eq!(request.method, Method::PUT);
+        assert_eq!(request.uri, "http://dummy/p/7");
+        assert_eq!(request.headers[AUTHORIZATION], "Bearer abc");
+        assert_eq!(request.body, r#"{"name": "{{name}}", "tags": [{{}}]}"#);
+        assert!(request.expected_status.contains(204));
+        assert!(!request.expected_status.contains(200));
+
+        let spec = RequestSpec::get(Uri::from_static("http://dummy/"));
+        let template = RequestTemplate::from(spec);
+        assert_eq!(template.variables().count(), 0);
+        assert!(template.render(&Variables::new(), &mut rng(None)).is_ok());
+    }
+}
@@ -0,0 +1,414 @@
+use std::{
+    fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use hyper::{http::uri::Scheme, Uri};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName,
+};
+use rustls_pemfile::Item;
+
+/// How the servers of `https` targets are verified.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// Trust the CA certificates in this PEM file instead of the built-in roots.
+    pub ca_file: Option<PathBuf>,
+    /// Accept any server certificate, e.g. a self-signed one.
+    pub insecure: bool,
+    /// Send this name (SNI) and verify the certificate against it instead of the host of the URI.
+    pub server_name: Option<String>,
+    /// Present this certificate to servers that ask for one (mutual TLS).
+    pub client_identity: Option<ClientIdentity>,
+}
+
+/// A client certificate chain and its private key.
+#[derive(Debug, Clone)]
+pub enum ClientIdentity {
+    /// A PEM file with the certificate chain and one with the private key. They may be the same.
+    Pem {
+        cert_file: PathBuf,
+        key_file: PathBuf,
+    },
+    /// A PKCS#12 (`.p12`/`.pfx`) archive with both.
+    Pkcs12 { file: PathBuf, password: String },
+}
+
+impl ClientIdentity {
+    fn load(&self) -> anyhow::Result<(Vec<Certificate>, PrivateKey)> {
+        match self {
+            ClientIdentity::Pem {
+                cert_file,
+                key_file,
+            } => Ok((read_certs(cert_file)?, read_private_key(key_file)?)),
+            ClientIdentity::Pkcs12 { file, password } => read_pkcs12(file, password),
+        }
+    }
+}
+
+/// What the connectors of a run need for TLS, built once from `TlsOptions`.
+#[derive(Clone)]
+pub struct TlsConfig {
+    pub client_config: ClientConfig,
+    pub server_name: Option<String>,
+}
+
+impl TlsOptions {
+    /// Returns the TLS configuration for `uri`, or `None` if it isn't an `https` URI.
+    pub fn config_for(&self, uri: &Uri) -> anyhow::Result<Option<TlsConfig>> {
+        if uri.scheme() != Some(&Scheme::HTTPS) {
+            return Ok(None);
+        }
+        let builder = ClientConfig::builder().with_safe_defaults();
+        let verifier: Arc<dyn ServerCertVerifier> = if self.insecure {
+            Arc::new(AcceptAnyCertificate)
+        } else {
+            Arc::new(WebPkiVerifier::new(self.root_store()?, None))
+        };
+        let builder = builder.with_custom_certificate_verifier(verifier);
+        let client_config = match &self.client_identity {
+            Some(identity) => {
+                let (certs, key) = identity.load()?;
+                builder
+                    .with_client_auth_cert(certs, key)
+                    .context("Invalid client certificate")?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Some(TlsConfig {
+            client_config,
+            server_name: self.server_name.clone(),
+        }))
+    }
+
+    fn root_store(&self) -> anyhow::Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        match &self.ca_file {
+            Some(path) => {
+                for cert in read_certs(path)? {
+                    roots
+                        .add(&cert)
+                        .with_context(|| format!("Invalid CA certificate in {}", path.display()))?;
+                }
+            }
+            None => roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    ta.subject,
+                    ta.spki,
+                    ta.name_constraints,
+                )
+            })),
+        }
+        Ok(roots)
+    }
+}
+
+/// Reads all certificates of a PEM file.
+fn read_certs(path: &Path) -> anyhow::Result<Vec<Certificate>> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Could not read certificates from {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("Invalid PEM file {}", path.display()))?;
+    anyhow::ensure!(!certs.is_empty(), "No certificates in {}", path.display());
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// Reads the first private key of a PEM file, in PKCS#8, PKCS#1 (RSA) or SEC1 (EC) format.
+fn read_private_key(path: &Path) -> anyhow::Result<PrivateKey> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Could not read private key from {}", path.display()))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .with_context(|| format!("Invalid PEM file {}", path.display()))?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .with_context(|| format!("No private key in {}", path.display()))
+}
+
+/// Reads the certificate chain and private key of a PKCS#12 archive.
+fn read_pkcs12(path: &Path, password: &str) -> anyhow::Result<(Vec<Certificate>, PrivateKey)> {
+    let data = fs::read(path)
+        .with_context(|| format!("Could not read PKCS#12 archive {}", path.display()))?;
+    let keystore = p12_keystore::KeyStore::from_pkcs12(&data, password)
+        .with_context(|| format!("Invalid PKCS#12 archive {}", path.display()))?;
+    let (_, chain) = keystore
+        .private_key_chain()
+        .with_context(|| format!("No private key in {}", path.display()))?;
+    let certs = chain
+        .chain()
+        .iter()
+        .map(|cert| Certificate(cert.as_der().to_vec()))
+        .collect();
+    Ok((certs, PrivateKey(chain.key().to_vec())))
+}
+
+/// Skips the verification of server certificates (`--insecure`).
+struct AcceptAnyCertificate;
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use hyper::{server::conn::Http, service::service_fn, Body, Response, Uri};
+    use rcgen::{BasicConstraints, CertificateParams, IsCa};
+    use rustls::{server::AllowAnyAuthenticatedClient, RootCertStore, ServerConfig};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::{ClientIdentity, TlsOptions};
+    use crate::{
+        mix::RequestMix, ErrorKind, HttpVersion, HyperClient, RequestSender, RequestSpec, Timeouts,
+    };
+
+    /// Serves `200 OK` over TLS with a self-signed certificate for `localhost`, requiring a client
+    /// certificate issued by `client_ca` if given. Returns the URI of the server and the
+    /// certificate in PEM.
+    async fn tls_server(client_ca: Option<&rcgen::Certificate>) -> (Uri, String) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let pem = cert.serialize_pem().unwrap();
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match client_ca {
+            Some(ca) => {
+                let mut roots = RootCertStore::empty();
+                roots
+                    .add(&rustls::Certificate(ca.serialize_der().unwrap()))
+                    .unwrap();
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let server_config = builder
+            .with_single_cert(
+                vec![rustls::Certificate(cert.serialize_der().unwrap())],
+                rustls::PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let service = service_fn(|_| async {
+                        Ok::<_, hyper::Error>(Response::new(Body::empty()))
+                    });
+                    let _ = Http::new().serve_connection(stream, service).await;
+                });
+            }
+        });
+        let uri = format!("https://localhost:{port}/").parse().unwrap();
+        (uri, pem)
+    }
+
+    /// Serves `200 OK` in plain text.
+    async fn http_server() -> Uri {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service =
+                    service_fn(|_| async { Ok::<_, hyper::Error>(Response::new(Body::empty())) });
+                tokio::spawn(Http::new().serve_connection(stream, service));
+            }
+        });
+        format!("http://localhost:{port}/").parse().unwrap()
+    }
+
+    /// Issues a client certificate. Returns the CA and the client certificate.
+    fn client_certificate() -> (rcgen::Certificate, rcgen::Certificate) {
+        let mut ca_params = CertificateParams::new(vec![]);
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+        let client =
+            rcgen::Certificate::from_params(CertificateParams::new(vec!["client".to_string()]))
+                .unwrap();
+        (ca, client)
+    }
+
+    fn temp_file(name: &str, content: impl AsRef<[u8]>) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{name}", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    async fn send(options: &TlsOptions, uri: &Uri) -> anyhow::Result<u16> {
+        let tls = options.config_for(uri).unwrap();
+        let client = HyperClient::new(Timeouts::default(), tls.as_ref(), HttpVersion::Http1, None);
+        client.send(&RequestSpec::get(uri.clone())).await
+    }
+
+    #[tokio::test]
+    async fn test_trusts_ca_file() {
+        let (uri, pem) = tls_server(None).await;
+        let path = temp_file(&format!("ca-{}.pem", uri.port_u16().unwrap()), pem);
+        let options = TlsOptions {
+            ca_file: Some(path.clone()),
+            ..TlsOptions::default()
+        };
+        assert_eq!(send(&options, &uri).await.unwrap(), 200);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_times_the_phases_of_new_connections() {
+        let (uri, _) = tls_server(None).await;
+        let options = TlsOptions {
+            insecure: true,
+            ..TlsOptions::default()
+        };
+        let tls = options.config_for(&uri).unwrap();
+        let client = HyperClient::new(Timeouts::default(), tls.as_ref(), HttpVersion::Http1, None);
+        let request = RequestSpec::get(uri);
+
+        let (status, stats) = client.send_with_stats(&request).await;
+        assert_eq!(status.unwrap(), 200);
+        assert!(stats.phases.to_array().iter().all(Option::is_some));
+        assert!(stats.written.is_some());
+
+        let (status, stats) = client.send_with_stats(&request).await;
+        assert_eq!(status.unwrap(), 200);
+        let phases = stats.phases;
+        assert_eq!((phases.dns, phases.connect, phases.tls), (None, None, None));
+        assert!(phases.ttfb.is_some() && phases.download.is_some());
+        assert!(stats.written.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mix_of_http_and_https_endpoints() {
+        let (https_uri, _) = tls_server(None).await;
+        let http_uri = http_server().await;
+        let endpoints = format!(r#"[{{"url": "{http_uri}"}}, {{"url": "{https_uri}"}}]"#);
+        let mix = RequestMix::parse(&endpoints, &RequestSpec::get(http_uri)).unwrap();
+        let options = TlsOptions {
+            insecure: true,
+            ..TlsOptions::default()
+        };
+        let tls = options.config_for(mix.tls_uri()).unwrap();
+        let client = HyperClient::new(Timeouts::default(), tls.as_ref(), HttpVersion::Http1, None);
+
+        let [http, https] = mix.endpoints() else {
+            panic!("expected two endpoints");
+        };
+        let (status, stats) = client.send_with_stats(&http.request).await;
+        assert_eq!(status.unwrap(), 200);
+        assert!(stats.phases.connect.is_some() && stats.phases.tls.is_none());
+        let (status, stats) = client.send_with_stats(&https.request).await;
+        assert_eq!(status.unwrap(), 200);
+        assert!(stats.phases.tls.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_self_signed_certificate() {
+        let (uri, _) = tls_server(None).await;
+        let error = send(&TlsOptions::default(), &uri).await.unwrap_err();
+        assert_eq!(ErrorKind::classify(&error), ErrorKind::TlsHandshake);
+    }
+
+    #[tokio::test]
+    async fn test_insecure_accepts_self_signed_certificate() {
+        let (uri, _) = tls_server(None).await;
+        let options = TlsOptions {
+            insecure: true,
+            ..TlsOptions::default()
+        };
+        assert_eq!(send(&options, &uri).await.unwrap(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_presents_pem_client_certificate() {
+        let (ca, client) = client_certificate();
+        let (uri, _) = tls_server(Some(&ca)).await;
+        let port = uri.port_u16().unwrap();
+        let cert_file = temp_file(
+            &format!("client-{port}.pem"),
+            client.serialize_pem_with_signer(&ca).unwrap(),
+        );
+        let key_file = temp_file(
+            &format!("client-{port}.key"),
+            client.serialize_private_key_pem(),
+        );
+        let options = TlsOptions {
+            insecure: true,
+            client_identity: Some(ClientIdentity::Pem {
+                cert_file: cert_file.clone(),
+                key_file: key_file.clone(),
+            }),
+            ..TlsOptions::default()
+        };
+        assert_eq!(send(&options, &uri).await.unwrap(), 200);
+        std::fs::remove_file(cert_file).unwrap();
+        std::fs::remove_file(key_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_presents_pkcs12_client_certificate() {
+        let (ca, client) = client_certificate();
+        let (uri, _) = tls_server(Some(&ca)).await;
+        let cert =
+            p12_keystore::Certificate::from_der(&client.serialize_der_with_signer(&ca).unwrap())
+                .unwrap();
+        let chain =
+            p12_keystore::PrivateKeyChain::new(client.serialize_private_key_der(), [1], [cert]);
+        let mut keystore = p12_keystore::KeyStore::new();
+        keystore.add_entry(
+            "client",
+            p12_keystore::KeyStoreEntry::PrivateKeyChain(chain),
+        );
+        let archive = keystore.writer("secret").write().unwrap();
+        let file = temp_file(&format!("client-{}.p12", uri.port_u16().unwrap()), archive);
+        let options = TlsOptions {
+            insecure: true,
+            client_identity: Some(ClientIdentity::Pkcs12 {
+                file: file.clone(),
+                password: "secret".to_string(),
+            }),
+            ..TlsOptions::default()
+        };
+        assert_eq!(send(&options, &uri).await.unwrap(), 200);
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_client_certificate_is_a_handshake_error() {
+        let (ca, _) = client_certificate();
+        let (uri, _) = tls_server(Some(&ca)).await;
+        let options = TlsOptions {
+            insecure: true,
+            ..TlsOptions::default()
+        };
+        let error = send(&options, &uri).await.unwrap_err();
+        assert_eq!(ErrorKind::classify(&error), ErrorKind::TlsHandshake);
+    }
+
+    #[test]
+    fn test_no_config_for_http() {
+        let uri = Uri::from_static("http://dummy");
+        assert!(TlsOptions::default().config_for(&uri).unwrap().is_none());
+    }
+}